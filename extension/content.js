@@ -1,6 +1,8 @@
 let onboarder_id = "not setup yet"
 let tag = `[Onboarder-${onboarder_id}]`;
 const textAreaId = "custom_notes_area";
+let metadataUrl = null;
+let mediaPath = null;
 
 let serverUrl = "https://localhost:5876/";
 chrome.storage.local.get("serverUrl", function (data) {
@@ -87,9 +89,9 @@ async function appendContent(content) {
     if (!content.endsWith("\n")) content += "\n";
     const next = existing + content;
     console.log(`${tag} appending content`, {existing, next});
-    await save(next);
     const note = document.getElementById("custom_notes_area");
     note.value = next;
+    await save(next);
 }
 
 function getVideoProgress() {
@@ -139,6 +141,13 @@ function addChips(videoArea) {
                 await downloadAudio();
             }
         },
+        {
+            text: "Download playlist",
+            description: "Download every video in the playlist that isn't downloaded yet",
+            action: async function() {
+                await downloadPlaylist();
+            }
+        },
         {
             text: "📁 Videos",
             description: "Open videos folder",
@@ -155,11 +164,32 @@ function addChips(videoArea) {
         },
         {
             text: "📝 Transcript",
-            description: "Open transcript website",
+            description: "Open the downloaded transcript, or the transcript website",
             action: async function() {
                 openTranscriptInNewTab();
             }
         },
+        {
+            text: "ℹ Metadata",
+            description: "Open the video metadata saved by the server",
+            action: async function() {
+                openMetadataInNewTab();
+            }
+        },
+        {
+            text: "▶ Local player",
+            description: "Open the downloaded video with these notes in the server's player",
+            action: async function() {
+                openLocalPlayer();
+            }
+        },
+        {
+            text: "🎞 Local file",
+            description: "Copy the path of the downloaded video",
+            action: async function() {
+                copyMediaPathToClipboard();
+            }
+        },
         {
             text: "✂ Copy to clipboard",
             description: "Copies video information to clipboard",
@@ -174,6 +204,20 @@ function addChips(videoArea) {
                 await downloadSubtitles();
             },
         },        
+        {
+            text: "📷 Snapshot",
+            description: "Add a frame of the downloaded video at the current time to the notes",
+            action: async function () {
+                await takeSnapshot();
+            },
+        },
+        {
+            text: "✂ Clip",
+            description: "Click at the start and again at the end to cut a clip from the downloaded video",
+            action: async function () {
+                await markClip(this);
+            },
+        },
     ];
     actions.forEach((action) => {
         const chip = document.createElement("button");
@@ -296,41 +340,148 @@ function attachLikeListeners() {
     console.log(`${tag} attached like listeners`, likeButton, dislikeButton);
 }
 
+// The server's version of the note and its text, as of the last load or save
+let noteVersion = null;
+let savedContent = null;
+// Saves go out one at a time, so each is made on top of the one before
+let saving = null;
+let pendingContent = null;
+
 function save(content) {
+    pendingContent = content;
+    if (!saving) {
+        saving = (async () => {
+            while (pendingContent !== null) {
+                const next = pendingContent;
+                pendingContent = null;
+                await sendNote(next);
+            }
+            saving = null;
+        })();
+    }
+    return saving;
+}
+
+async function sendNote(content) {
     // Build the note ID from the v= slug + the title of the video
     const id = getNoteId();
     console.log(`${tag} saving`, {id, content});
 
     // Create a POST request to the Rust HTTP server
-    return fetch(`${serverUrl}/set_note`, {
-        method: "POST",
-        headers: {
-            "Content-Type": "application/json",
-        },
-        body: JSON.stringify({
-            id,
-            content,
-        }),
-    })
-        .then((response) => response.text())
-        .then((data) => {
-            console.log(`${tag} Success:`, data);
-        })
-        .catch((error) => {
-            console.error(`${tag} Error:`, error);
+    try {
+        const resp = await fetch(`${serverUrl}/set_note`, {
+            method: "POST",
+            headers: {
+                "Content-Type": "application/json",
+            },
+            body: JSON.stringify({
+                id,
+                content,
+                version: noteVersion ?? undefined,
+            }),
         });
+        if (resp.status == 409) {
+            // Changed on the server, like a tag rename: redo our edit on top of it
+            const current = await resp.json();
+            console.log(`${tag} note changed on the server, merging`, current);
+            const textArea = document.getElementById(textAreaId);
+            const local = textArea.value;
+            const merged = rebase(savedContent ?? "", local, current.content);
+            noteVersion = current.version;
+            savedContent = current.content;
+            const next = merged ?? current.content;
+            if (merged === null) {
+                console.warn(`${tag} could not merge the last edit, taking the server's note`);
+            }
+            replaceText(textArea, next);
+            if (next != current.content) pendingContent = next;
+            return;
+        }
+        if (resp.status != 200) {
+            console.error(`${tag} Failed to save, status code: ${resp.status}`);
+            return;
+        }
+        const data = await resp.json();
+        noteVersion = data.version;
+        savedContent = content;
+        console.log(`${tag} Success:`, data);
+    } catch (error) {
+        console.error(`${tag} Error:`, error);
+    }
+}
+
+// Applies the change from base to local onto server, or returns null if the
+// text it touched can't be found there exactly once
+function rebase(base, local, server) {
+    let start = 0;
+    while (start < base.length && start < local.length && base[start] == local[start]) start++;
+    let end = 0;
+    while (
+        end < base.length - start &&
+        end < local.length - start &&
+        base[base.length - 1 - end] == local[local.length - 1 - end]
+    ) end++;
+    const removed = base.slice(start, base.length - end);
+    const inserted = local.slice(start, local.length - end);
+    if (removed == "" && inserted == "") return server;
+    // As much of the text around it as the server still has, but enough to be unique
+    const lengths = [40, 20, 10, 5, 0];
+    const tries = lengths
+        .flatMap((beforeLength) => lengths.map((afterLength) => [beforeLength, afterLength]))
+        .sort((a, b) => b[0] + b[1] - (a[0] + a[1]));
+    for (const [beforeLength, afterLength] of tries) {
+        const before = base.slice(Math.max(0, start - beforeLength), start);
+        const after = base.slice(base.length - end, base.length - end + afterLength);
+        const around = before + removed + after;
+        if (around == "") continue;
+        const at = server.indexOf(around);
+        if (at == -1 || server.indexOf(around, at + 1) != -1) continue;
+        const from = at + before.length;
+        return server.slice(0, from) + inserted + server.slice(from + removed.length);
+    }
+    return null;
+}
+
+// Swaps the text box's text, keeping the cursor next to the same text
+function replaceText(textArea, text) {
+    const old = textArea.value;
+    const cursor = textArea.selectionStart;
+    let same = 0;
+    while (same < old.length && same < text.length && old[same] == text[same]) same++;
+    textArea.value = text;
+    const moved = cursor > same ? cursor + text.length - old.length : cursor;
+    textArea.selectionStart = textArea.selectionEnd = Math.max(0, Math.min(moved, text.length));
+}
+
+// Returns the files of the given kinds already on disk for this video, or null if the check failed
+async function findExisting(kinds) {
+    const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
+    const resp = await fetch(`${serverUrl}/exists?search=${videoId}`);
+    if (resp.status == 404) return [];
+    if (resp.status != 200) {
+        console.error(`${tag} Failed to check for existing downloads, status code: ${resp.status}`);
+        const text = await resp.text();
+        alert(`Failed to check for existing downloads!\n${text}`);
+        return null;
+    }
+    const data = await resp.json();
+    return data.matches.filter((match) => kinds.includes(match.kind));
+}
+
+function describeMatches(matches) {
+    return matches
+        .map((match) => `${match.kind}: ${match.path} (${(match.size / 1024 / 1024).toFixed(1)} MiB)`)
+        .join("\n");
 }
 
 async function downloadVideo() {
     console.log(`${tag} Ensuring video has not already been downloaded before downloading`);
     {
-        const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
-        const resp = await fetch(`${serverUrl}/exists?search=${videoId}`);
-        // ensure response is 404
-        if (resp.status != 404) {
+        const existing = await findExisting(["video"]);
+        if (existing === null) return;
+        if (existing.length > 0) {
             console.log(`${tag} Video already downloaded, not downloading again`);
-            const text = await resp.text();
-            alert(`Video already downloaded!\n${text}`);
+            alert(`Video already downloaded!\n${describeMatches(existing)}`);
             return;
         }
     }
@@ -346,13 +497,13 @@ async function downloadVideo() {
             body: window.location.href.split("&")[0],
         });
         if (resp.status == 200) {
-            const fileId = await resp.text();
+            const job = await resp.json();
             const content =
                 getCurrentNoteContent() +
-                `\n${new Date().toString()} --- Download started for "${fileId}"`;
-            await save(content);
+                `\n${new Date().toString()} --- Download started for "${job.filename}" (job ${job.job_id}, log: ${serverUrl}${job.log_url})`;
             const note = document.getElementById("custom_notes_area");
             note.value = content;
+            await save(content);
         } else {
             const content =
                 getCurrentNoteContent() +
@@ -367,13 +518,11 @@ async function downloadVideo() {
 async function downloadAudio() {
     console.log(`${tag} Ensuring audio has not already been downloaded before downloading`);
     {
-        const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
-        const resp = await fetch(`${serverUrl}/exists?search=${videoId}`);
-        // ensure response is 404
-        if (resp.status != 404) {
+        const existing = await findExisting(["audio", "video"]);
+        if (existing === null) return;
+        if (existing.length > 0) {
             console.log(`${tag} audio already downloaded, not downloading again`);
-            const text = await resp.text();
-            alert(`Video already downloaded!\n${text}`);
+            alert(`Video already downloaded!\n${describeMatches(existing)}`);
             return;
         }
     }
@@ -389,13 +538,13 @@ async function downloadAudio() {
             body: window.location.href.split("&")[0],
         });
         if (resp.status == 200) {
-            const fileId = await resp.text();
+            const job = await resp.json();
             const content =
                 getCurrentNoteContent() +
-                `\n${new Date().toString()} --- Download started for "${fileId}"`;
-            await save(content);
+                `\n${new Date().toString()} --- Download started for "${job.filename}" (job ${job.job_id}, log: ${serverUrl}${job.log_url})`;
             const note = document.getElementById("custom_notes_area");
             note.value = content;
+            await save(content);
         } else {
             const content =
                 getCurrentNoteContent() +
@@ -406,6 +555,32 @@ async function downloadAudio() {
     }
 }
 
+async function downloadPlaylist() {
+    if (!new URL(window.location.href).searchParams.has("list")) {
+        alert("This video is not being played from a playlist");
+        return;
+    }
+    // The whole URL is needed here, the playlist id is in the list= parameter
+    const resp = await fetch(`${serverUrl}/download_playlist`, {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/text",
+        },
+        body: window.location.href,
+    });
+    if (resp.status == 200) {
+        const job = await resp.json();
+        const content = getCurrentNoteContent() +
+            `\n${new Date().toString()} --- Playlist download started (job ${job.job_id}, progress: ${serverUrl}/jobs/${job.job_id}, log: ${serverUrl}${job.log_url})`;
+        document.getElementById("custom_notes_area").value = content;
+        await save(content);
+    } else {
+        const text = await resp.text();
+        console.error(`${tag} Failed to download playlist, status code: ${resp.status}`);
+        alert(`Failed to download playlist!\n${text}`);
+    }
+}
+
 async function openVideosFolder() {
     console.log(`${tag} Opening videos folder`);
     await fetch(`${serverUrl}/open_videos_folder`, {
@@ -427,7 +602,7 @@ async function openNotesFolder() {
     });
 }
 
-function openTranscriptInNewTab() {
+async function openTranscriptInNewTab() {
     // Create a URL object from the current window location
     var currentUrl = new URL(window.location.href);
     
@@ -435,7 +610,17 @@ function openTranscriptInNewTab() {
     var videoId = currentUrl.searchParams.get('v');
 
     if (videoId) {
+        // Prefer subtitles we already downloaded
+        var localUrl = `${serverUrl}/transcript/${videoId}?format=text`;
         var transcriptUrl = 'https://youtubetranscript.com/?v=' + videoId;
+        try {
+            const resp = await fetch(localUrl);
+            if (resp.ok) {
+                transcriptUrl = localUrl;
+            }
+        } catch (error) {
+            console.error(`${tag} Error checking for a local transcript:`, error);
+        }
         
         // Open the transcript URL in a new tab
         window.open(transcriptUrl, '_blank');
@@ -444,6 +629,27 @@ function openTranscriptInNewTab() {
     }
 }
 
+function openMetadataInNewTab() {
+    if (metadataUrl) {
+        window.open(`${serverUrl}${metadataUrl}`, '_blank');
+    } else {
+        console.error(`${tag} Note has no metadata link`);
+    }
+}
+
+function openLocalPlayer() {
+    const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
+    window.open(`${serverUrl}/player/${videoId}`, '_blank');
+}
+
+function copyMediaPathToClipboard() {
+    if (mediaPath) {
+        navigator.clipboard.writeText(mediaPath);
+    } else {
+        alert("No local copy of this video is known yet. Reload after the download finishes.");
+    }
+}
+
 function copyVideoDetailsToClipboard() {
     const videoTitle = document.querySelector("ytd-watch-metadata #title").innerText;
     // remove query params
@@ -459,13 +665,11 @@ async function downloadSubtitles() {
     
     // Optional: check if subtitles have already been downloaded
     {
-        const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
-        const resp = await fetch(`${serverUrl}/exists?search=${videoId}`);
-        // Adjust this check as needed; if the file exists, then skip downloading again
-        if (resp.status != 404) {
+        const existing = await findExisting(["subtitle"]);
+        if (existing === null) return;
+        if (existing.length > 0) {
             console.log(`${tag} Subtitles already downloaded, not downloading again`);
-            const text = await resp.text();
-            alert(`Subtitles already downloaded!\n${text}`);
+            alert(`Subtitles already downloaded!\n${describeMatches(existing)}`);
             return;
         }
     }
@@ -480,11 +684,11 @@ async function downloadSubtitles() {
         body: window.location.href.split("&")[0],
     });
     if (resp.status == 200) {
-        const fileId = await resp.text();
+        const job = await resp.json();
         const content = getCurrentNoteContent() +
-            `\n${new Date().toString()} --- Subtitles download started for "${fileId}"`;
-        await save(content);
+            `\n${new Date().toString()} --- Subtitles download started (job ${job.job_id}, log: ${serverUrl}${job.log_url})`;
         document.getElementById("custom_notes_area").value = content;
+        await save(content);
     } else {
         const content = getCurrentNoteContent() +
             `\nFailed to download subtitles, status code: ${resp.status}`;
@@ -493,6 +697,109 @@ async function downloadSubtitles() {
 }
 
 
+// Polls a job until it finishes, returning it
+async function waitForJob(jobId) {
+    while (true) {
+        const resp = await fetch(`${serverUrl}/jobs/${jobId}`);
+        if (resp.status != 200) {
+            throw new Error(`Failed to check on job ${jobId}, status code: ${resp.status}`);
+        }
+        const job = await resp.json();
+        if (job.status != "running") return job;
+        await sleep(1000);
+    }
+}
+
+// Adds the lines a job made for the note, each after the line it names or at the end
+async function insertNoteLines(noteLines) {
+    const lines = getCurrentNoteContent().split("\n");
+    const atEnd = [];
+    // Backwards, so lines that go after the same line keep their order
+    for (const noteLine of [...noteLines].reverse()) {
+        if (lines.includes(noteLine.line)) continue;
+        const index = noteLine.after === undefined
+            ? -1
+            : lines.findIndex((line) => line == noteLine.after);
+        if (index == -1) {
+            atEnd.unshift(noteLine.line);
+        } else {
+            lines.splice(index + 1, 0, noteLine.line);
+        }
+    }
+    if (atEnd.length > 0) {
+        const trailing = lines.length > 0 && lines[lines.length - 1] == "";
+        lines.splice(trailing ? lines.length - 1 : lines.length, 0, ...atEnd);
+    }
+    const next = lines.join("\n");
+    document.getElementById(textAreaId).value = next;
+    await save(next);
+}
+
+// Waits for a job and adds what it made to the note, or says why it failed
+async function finishJobInNote(jobId, what) {
+    try {
+        const job = await waitForJob(jobId);
+        if (job.status == "succeeded") {
+            await insertNoteLines(job.note_lines || []);
+        } else {
+            alert(`${what} failed!\n${job.error}\nLog: ${serverUrl}/jobs/${jobId}/log`);
+        }
+    } catch (error) {
+        console.error(`${tag} Error:`, error);
+    }
+}
+
+async function takeSnapshot() {
+    const video = document.getElementsByClassName("html5-main-video")[0];
+    const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
+    console.log(`${tag} Taking snapshot`, {videoId, position: video.currentTime});
+    const resp = await fetch(`${serverUrl}/snapshot`, {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify({video_id: videoId, position: video.currentTime}),
+    });
+    if (resp.status != 200) {
+        const text = await resp.text();
+        alert(`Failed to take a snapshot!\n${text}`);
+        return;
+    }
+    const job = await resp.json();
+    await finishJobInNote(job.job_id, "Snapshot");
+}
+
+let clipStart = null;
+
+async function markClip(chip) {
+    const video = document.getElementsByClassName("html5-main-video")[0];
+    if (clipStart === null) {
+        clipStart = video.currentTime;
+        chip.innerText = "✂ End clip";
+        return;
+    }
+    const start = Math.min(clipStart, video.currentTime);
+    const end = Math.max(clipStart, video.currentTime);
+    clipStart = null;
+    chip.innerText = "✂ Clip";
+    const videoId = document.querySelector("ytd-watch-metadata").getAttribute("video-id");
+    console.log(`${tag} Clipping`, {videoId, start, end});
+    const resp = await fetch(`${serverUrl}/clip`, {
+        method: "POST",
+        headers: {
+            "Content-Type": "application/json",
+        },
+        body: JSON.stringify({video_id: videoId, start, end}),
+    });
+    if (resp.status != 200) {
+        const text = await resp.text();
+        alert(`Failed to start the clip!\n${text}`);
+        return;
+    }
+    const job = await resp.json();
+    await finishJobInNote(job.job_id, "Clip");
+}
+
 async function sleep(ms) {
     return new Promise((resolve) => setTimeout(resolve, ms));
 }
@@ -553,6 +860,10 @@ async function setup() {
             );
             const data = await resp.json();
             content = data.content;
+            noteVersion = data.version || null;
+            savedContent = content;
+            metadataUrl = data.metadata_url || null;
+            mediaPath = data.media_path || null;
             console.log(`${tag} received existing content`, {length: content.length, content});
         }
         addTextArea(videoArea, content);
@@ -0,0 +1,307 @@
+use crate::subtitles::Segment;
+use crate::timestamps::deep_link;
+use crate::timestamps::format_timestamp;
+use crate::timestamps::text_after;
+use crate::timestamps::timed_lines;
+use crate::timestamps::LineKind;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+impl Format {
+    pub fn from_query(format: Option<&str>) -> Result<Format, String> {
+        match format {
+            None | Some("md") | Some("markdown") => Ok(Format::Markdown),
+            Some("html") => Ok(Format::Html),
+            Some(other) => Err(format!("Unknown format {:?}, expected md or html", other)),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// The margin mark for an event the extension logs, for the events worth
+/// seeing while reviewing. Play and stop events are left out.
+fn event_mark(event: &str) -> Option<&'static str> {
+    match event {
+        "paused" => Some("⏸"),
+        "👍" => Some("👍"),
+        "👎" => Some("👎"),
+        "➖👍" => Some("➖👍"),
+        "➖👎" => Some("➖👎"),
+        _ => None,
+    }
+}
+
+enum Row {
+    Speech {
+        start: f64,
+        text: String,
+        marks: Vec<&'static str>,
+    },
+    Note {
+        position: f64,
+        text: String,
+    },
+    /// An event with no transcript to put it next to.
+    Event {
+        position: f64,
+        mark: &'static str,
+    },
+}
+
+/// Interleaves `segments` with the note's timed lines. Notes follow the
+/// segment being said when they were written, and events mark it.
+fn rows(segments: &[Segment], note: &str) -> Vec<Row> {
+    let lines = note.lines().collect::<Vec<&str>>();
+    // For each note line, the segment it goes after (None: before the first)
+    let mut notes: Vec<(Option<usize>, Row)> = Vec::new();
+    let mut marks: Vec<Vec<&'static str>> = vec![Vec::new(); segments.len()];
+    for timed in timed_lines(note) {
+        let segment = segments
+            .iter()
+            .rposition(|segment| segment.start <= timed.position);
+        let text = match timed.kind {
+            LineKind::Event(event) => {
+                let Some(mark) = event_mark(&event) else {
+                    continue;
+                };
+                match segment {
+                    Some(segment) => marks[segment].push(mark),
+                    None => notes.push((
+                        None,
+                        Row::Event {
+                            position: timed.position,
+                            mark,
+                        },
+                    )),
+                }
+                continue;
+            }
+            LineKind::Timestamp => text_after(&lines, timed.index).join("\n"),
+            LineKind::Text => lines[timed.index].trim().to_string(),
+        };
+        if !text.is_empty() {
+            notes.push((
+                segment,
+                Row::Note {
+                    position: timed.position,
+                    text,
+                },
+            ));
+        }
+    }
+    let position = |row: &Row| match row {
+        Row::Speech { start, .. } => *start,
+        Row::Note { position, .. } | Row::Event { position, .. } => *position,
+    };
+    notes.sort_by(|a, b| position(&a.1).total_cmp(&position(&b.1)));
+
+    let mut notes = notes.into_iter().peekable();
+    let mut rows = Vec::new();
+    while let Some((_, row)) = notes.next_if(|(segment, _)| segment.is_none()) {
+        rows.push(row);
+    }
+    for (index, (segment, marks)) in segments.iter().zip(marks).enumerate() {
+        rows.push(Row::Speech {
+            start: segment.start,
+            text: segment.text.clone(),
+            marks,
+        });
+        while let Some((_, row)) = notes.next_if(|(segment, _)| *segment == Some(index)) {
+            rows.push(row);
+        }
+    }
+    rows.extend(notes.map(|(_, row)| row));
+    rows
+}
+
+/// One document with the transcript, the notes and the events of a video,
+/// each at its position with a link to that moment on YouTube.
+pub fn render(
+    format: Format,
+    video_id: &str,
+    title: &str,
+    segments: &[Segment],
+    note: Option<&str>,
+) -> String {
+    let rows = rows(segments, note.unwrap_or_default());
+    match format {
+        Format::Markdown => markdown(video_id, title, &rows),
+        Format::Html => html(video_id, title, &rows),
+    }
+}
+
+fn markdown(video_id: &str, title: &str, rows: &[Row]) -> String {
+    let link = |at: f64| format!("[{}]({})", format_timestamp(at), deep_link(video_id, at));
+    let mut text = format!(
+        "# {}\n\n<https://www.youtube.com/watch?v={}>\n\n",
+        title, video_id
+    );
+    for row in rows {
+        match row {
+            Row::Speech {
+                start,
+                text: speech,
+                marks,
+            } => {
+                let marks = if marks.is_empty() {
+                    String::new()
+                } else {
+                    format!("{} ", marks.join(""))
+                };
+                text.push_str(&format!("{}{} {}\n\n", marks, link(*start), speech));
+            }
+            Row::Note {
+                position,
+                text: note,
+            } => {
+                let quoted = note.replace('\n', "\n> ");
+                text.push_str(&format!("> 📝 {} {}\n\n", link(*position), quoted));
+            }
+            Row::Event { position, mark } => {
+                text.push_str(&format!("{} {}\n\n", mark, link(*position)));
+            }
+        }
+    }
+    text
+}
+
+fn html(video_id: &str, title: &str, rows: &[Row]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    };
+    let link = |at: f64| {
+        format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&deep_link(video_id, at)),
+            format_timestamp(at)
+        )
+    };
+    let mut body = String::new();
+    for row in rows {
+        let (class, margin, at, text) = match row {
+            Row::Speech { start, text, marks } => ("speech", marks.join(""), *start, escape(text)),
+            Row::Note { position, text } => (
+                "note",
+                String::new(),
+                *position,
+                escape(text).replace('\n', "<br>"),
+            ),
+            Row::Event { position, mark } => ("event", mark.to_string(), *position, String::new()),
+        };
+        body.push_str(&format!(
+            "<tr class=\"{}\"><td class=\"margin\">{}</td><td class=\"time\">{}</td><td>{}</td></tr>\n",
+            class,
+            margin,
+            link(at),
+            text
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+         <style>\n\
+         body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; }}\n\
+         td {{ padding: 0.2em 0.5em; vertical-align: top; }}\n\
+         td.margin {{ width: 3em; text-align: right; }}\n\
+         td.time a {{ color: #888888; text-decoration: none; }}\n\
+         tr.note td:last-child {{ background-color: #fff8dc; border-left: 3px solid #e0b000; }}\n\
+         </style>\n</head>\n<body>\n<h1>{title}</h1>\n\
+         <p><a href=\"https://www.youtube.com/watch?v={video_id}\">https://www.youtube.com/watch?v={video_id}</a></p>\n\
+         <table>\n{body}</table>\n</body>\n</html>\n",
+        title = escape(title),
+        video_id = escape(video_id),
+        body = body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments() -> Vec<Segment> {
+        [
+            (2.0, "hello and welcome"),
+            (10.0, "today we <build> a thing"),
+        ]
+        .into_iter()
+        .map(|(start, text)| Segment {
+            start,
+            end: start + 8.0,
+            text: text.to_string(),
+        })
+        .collect()
+    }
+
+    const NOTE: &str = "\
+Sat Oct 19 2026 05:38:49 GMT-0400 (Eastern Daylight Time) --- 00:00:01 / 00:10:00 (0.17%) --- 👍
+Sat Oct 19 2026 05:38:50 GMT-0400 (Eastern Daylight Time) --- 00:00:01 / 00:10:00 (0.17%) --- playing
+- 0:30 after everything
+video current time 00:00:05 / 00:10:00 (0.83%) at Sat Oct 19 2026 05:41:02 GMT-0400 (Eastern Daylight Time)
+a note
+on two lines
+Sat Oct 19 2026 05:41:10 GMT-0400 (Eastern Daylight Time) --- 00:00:12 / 00:10:00 (2.00%) --- paused
+";
+
+    #[test]
+    fn notes_and_events_follow_what_was_said() {
+        let text = render(
+            Format::Markdown,
+            "aaaaaaaaaaa",
+            "Talk",
+            &segments(),
+            Some(NOTE),
+        );
+        let link = |at: &str, seconds: u64| {
+            format!(
+                "[{}](https://www.youtube.com/watch?v=aaaaaaaaaaa&t={}s)",
+                at, seconds
+            )
+        };
+        assert_eq!(
+            text,
+            format!(
+                "# Talk\n\n<https://www.youtube.com/watch?v=aaaaaaaaaaa>\n\n\
+                 👍 {}\n\n\
+                 {} hello and welcome\n\n\
+                 > 📝 {} a note\n> on two lines\n\n\
+                 ⏸ {} today we <build> a thing\n\n\
+                 > 📝 {} - 0:30 after everything\n\n",
+                link("0:01", 1),
+                link("0:02", 2),
+                link("0:05", 5),
+                link("0:10", 10),
+                link("0:30", 30),
+            )
+        );
+    }
+
+    #[test]
+    fn notes_without_transcript() {
+        let text = render(Format::Markdown, "aaaaaaaaaaa", "Talk", &[], Some(NOTE));
+        let rows = text.lines().filter(|line| !line.is_empty()).skip(2);
+        let starts = rows
+            .map(|line| line.split(" [").next().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec!["👍", "> 📝", "> on two lines", "⏸", "> 📝"]);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let html = render(Format::Html, "aaaaaaaaaaa", "<Talk>", &segments(), None);
+        assert!(html.contains("<title>&lt;Talk&gt;</title>"));
+        assert!(html.contains("<td>today we &lt;build&gt; a thing</td>"));
+        assert!(html.contains("href=\"https://www.youtube.com/watch?v=aaaaaaaaaaa&amp;t=10s\""));
+    }
+}
@@ -0,0 +1,387 @@
+use crate::downloader::MediaKind;
+use crate::file_search::FileSearch;
+use crate::youtube::is_video_id;
+use chrono::DateTime;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::debug;
+use tracing::info;
+use tracing::warn;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileKind {
+    Video,
+    Audio,
+    Subtitle,
+    InfoJson,
+}
+
+impl FileKind {
+    pub fn from_path(path: &Path) -> Option<FileKind> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".info.json") {
+            return Some(FileKind::InfoJson);
+        }
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "mp4" | "mkv" | "webm" | "mov" | "avi" | "flv" | "m4v" => Some(FileKind::Video),
+            "m4a" | "mp3" | "opus" | "ogg" | "wav" | "flac" | "aac" => Some(FileKind::Audio),
+            "vtt" | "srt" | "ass" | "ssa" | "lrc" => Some(FileKind::Subtitle),
+            _ => None,
+        }
+    }
+
+    /// The kind of file a download profile of `kind` produces.
+    pub fn for_media(kind: MediaKind) -> FileKind {
+        match kind {
+            MediaKind::Video => FileKind::Video,
+            MediaKind::Audio => FileKind::Audio,
+            MediaKind::Subtitles => FileKind::Subtitle,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogFile {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub size: u64,
+    pub modified: Option<DateTime<Local>>,
+    /// The entry of `search_dirs` the file lives under, if any.
+    pub search_dir: Option<PathBuf>,
+}
+
+impl CatalogFile {
+    fn from_path(path: &Path, search_dirs: &[PathBuf]) -> Option<CatalogFile> {
+        let kind = FileKind::from_path(path)?;
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(CatalogFile {
+            path: path.to_path_buf(),
+            kind,
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Local>::from),
+            search_dir: search_dirs
+                .iter()
+                .find(|dir| path.starts_with(dir))
+                .cloned(),
+        })
+    }
+}
+
+/// A file found on disk, as reported by `/exists`.
+#[derive(Serialize, Clone, Debug)]
+pub struct FileMatch {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<DateTime<Local>>,
+    /// Unset for files that aren't media, subtitles or info json.
+    pub kind: Option<FileKind>,
+    pub search_dir: Option<PathBuf>,
+    pub video_id: Option<String>,
+}
+
+impl FileMatch {
+    pub fn from_catalog(video_id: &str, file: &CatalogFile) -> Self {
+        FileMatch {
+            path: file.path.clone(),
+            size: file.size,
+            modified: file.modified,
+            kind: Some(file.kind),
+            search_dir: file.search_dir.clone(),
+            video_id: Some(video_id.to_string()),
+        }
+    }
+
+    pub fn from_path(path: &Path, search_dirs: &[PathBuf]) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(FileMatch {
+            path: path.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::<Local>::from),
+            kind: FileKind::from_path(path),
+            search_dir: search_dirs
+                .iter()
+                .find(|dir| path.starts_with(dir))
+                .cloned(),
+            video_id: path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(video_id_from_filename),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogEntry {
+    pub video_id: String,
+    pub title: Option<String>,
+    pub channel: Option<String>,
+    pub files: Vec<CatalogFile>,
+}
+
+impl CatalogEntry {
+    pub fn has_kind(&self, kind: FileKind) -> bool {
+        self.files.iter().any(|file| file.kind == kind)
+    }
+
+    /// The newest video file still on disk, or failing that the newest audio file.
+    pub fn media_path(&self) -> Option<&Path> {
+        [FileKind::Video, FileKind::Audio]
+            .into_iter()
+            .find_map(|kind| {
+                self.files
+                    .iter()
+                    .filter(|file| file.kind == kind && file.path.exists())
+                    .max_by_key(|file| file.modified)
+            })
+            .map(|file| file.path.as_path())
+    }
+}
+
+/// What we have downloaded, keyed by video id and persisted as JSON in the
+/// data dir.
+pub struct Catalog {
+    path: PathBuf,
+    entries: BTreeMap<String, CatalogEntry>,
+}
+
+impl Catalog {
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let entries = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?
+        } else {
+            BTreeMap::new()
+        };
+        Ok(Catalog { path, entries })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let tmp = self.path.with_extension("json.tmp");
+        let content = serde_json::to_string_pretty(&self.entries).unwrap();
+        std::fs::write(&tmp, content)
+            .map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, &self.path)
+            .map_err(|e| format!("failed to replace {}: {}", self.path.display(), e))
+    }
+
+    pub fn get(&self, video_id: &str) -> Option<&CatalogEntry> {
+        self.entries.get(video_id)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &CatalogEntry> {
+        self.entries.values()
+    }
+
+    fn add(&mut self, video_id: &str, file: CatalogFile) {
+        let entry = self
+            .entries
+            .entry(video_id.to_string())
+            .or_insert_with(|| CatalogEntry {
+                video_id: video_id.to_string(),
+                title: None,
+                channel: None,
+                files: Vec::new(),
+            });
+        if file.kind == FileKind::InfoJson {
+            if let Some((_, title, channel)) = read_info_json(&file.path) {
+                entry.title = title.or(entry.title.take());
+                entry.channel = channel.or(entry.channel.take());
+            }
+        }
+        match entry.files.iter_mut().find(|f| f.path == file.path) {
+            Some(existing) => *existing = file,
+            None => entry.files.push(file),
+        }
+    }
+
+    /// Records the output of a finished download job.
+    pub fn record_files(&mut self, video_id: &str, files: &[PathBuf], search_dirs: &[PathBuf]) {
+        for path in files {
+            if let Some(file) = CatalogFile::from_path(path, search_dirs) {
+                self.add(video_id, file);
+            }
+        }
+    }
+
+    /// Forgets files of one video that no longer exist, and the video if it
+    /// is left without files.
+    pub fn forget_missing_of(&mut self, video_id: &str) {
+        let Some(entry) = self.entries.get_mut(video_id) else {
+            return;
+        };
+        entry.files.retain(|file| file.path.exists());
+        if entry.files.is_empty() {
+            self.entries.remove(video_id);
+        }
+    }
+
+    /// Forgets files that no longer exist, and videos left without files.
+    pub fn forget_missing(&mut self) {
+        for entry in self.entries.values_mut() {
+            entry.files.retain(|file| file.path.exists());
+        }
+        self.entries.retain(|_, entry| !entry.files.is_empty());
+    }
+
+    /// Forgets files that no longer exist and merges in the result of [`scan`].
+    pub fn merge_scan(&mut self, found: Vec<(String, CatalogFile)>) {
+        self.forget_missing();
+        for (video_id, file) in found {
+            self.add(&video_id, file);
+        }
+    }
+}
+
+/// Walks `search_dirs` for media belonging to a video. This touches the disk
+/// a lot, so it is done without holding the catalog lock.
+pub fn scan(file_search: &mut FileSearch, search_dirs: &[PathBuf]) -> Vec<(String, CatalogFile)> {
+    let mut found = Vec::new();
+    for dir in search_dirs {
+        let files = match file_search.files(dir) {
+            Ok(it) => it,
+            Err(err) => {
+                warn!("{}", err);
+                continue;
+            }
+        };
+        for (video_id, path) in identify(&files) {
+            if let Some(file) = CatalogFile::from_path(&path, search_dirs) {
+                found.push((video_id, file));
+            }
+        }
+    }
+    found
+}
+
+/// Rescans `search_dirs` in the background and saves the updated catalog.
+/// Returns how many files were found.
+pub async fn rescan(
+    catalog: &Mutex<Catalog>,
+    file_search: Arc<Mutex<FileSearch>>,
+    search_dirs: Vec<PathBuf>,
+) -> Result<usize, String> {
+    let found =
+        tokio::task::spawn_blocking(move || scan(&mut file_search.blocking_lock(), &search_dirs))
+            .await
+            .map_err(|e| format!("catalog scan panicked: {}", e))?;
+    let count = found.len();
+    let mut catalog = catalog.lock().await;
+    catalog.merge_scan(found);
+    info!(
+        "Catalog scan found {} files, catalog has {} videos",
+        count,
+        catalog.entries.len()
+    );
+    catalog.save()?;
+    Ok(count)
+}
+
+/// Pairs media files with video ids, using the `[id]` yt-dlp puts in file
+/// names or, failing that, a `.info.json` sidecar sharing the file's stem.
+fn identify(files: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut sidecars = HashMap::new();
+    for path in files {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if let Some(stem) = name.strip_suffix(".info.json") {
+            if let Some((id, _, _)) = read_info_json(path) {
+                sidecars.insert(path.with_file_name(stem), id);
+            }
+        }
+    }
+
+    let mut identified = Vec::new();
+    for path in files {
+        if FileKind::from_path(path).is_none() {
+            continue;
+        }
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        // The sidecar's stem is the part of the name before one of its dots,
+        // the longest such match being the most specific
+        let id = video_id_from_filename(name).or_else(|| {
+            name.rmatch_indices('.')
+                .find_map(|(end, _)| sidecars.get(&path.with_file_name(&name[..end])))
+                .cloned()
+        });
+        match id {
+            Some(id) => identified.push((id, path.clone())),
+            None => debug!("No video id for {}", path.display()),
+        }
+    }
+    identified
+}
+
+/// Finds the last `[...]` in a file name that holds a video id.
+pub fn video_id_from_filename(name: &str) -> Option<String> {
+    name.rmatch_indices('[').find_map(|(start, _)| {
+        let rest = &name[start + 1..];
+        let id = &rest[..rest.find(']')?];
+        is_video_id(id).then(|| id.to_string())
+    })
+}
+
+/// Reads the id, title and channel out of a yt-dlp `.info.json` file.
+fn read_info_json(path: &Path) -> Option<(String, Option<String>, Option<String>)> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let info: serde_json::Value = serde_json::from_str(&content).ok()?;
+    let field = |name: &str| info[name].as_str().map(String::from);
+    let id = field("id").filter(|id| is_video_id(id))?;
+    Some((id, field("title"), field("channel").or(field("uploader"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_uses_brackets_then_sidecars() {
+        let dir = std::env::temp_dir().join(format!("onboarder-catalog-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::write(dir.join("Talk.info.json"), r#"{"id": "aaaaaaaaaaa"}"#).unwrap();
+        std::fs::write(dir.join("Talk.v2.info.json"), r#"{"id": "bbbbbbbbbbb"}"#).unwrap();
+        let files = [
+            "Named [ccccccccccc].mp4",
+            "Talk.mp4",
+            "Talk.en.vtt",
+            "Talk.v2.mp4",
+            "Talk.info.json",
+            "Talk.v2.info.json",
+            "other/Talk.mp4",
+            "Unknown.mp4",
+        ]
+        .map(|name| dir.join(name));
+
+        let identified = identify(&files);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(
+            identified,
+            vec![
+                (
+                    "ccccccccccc".to_string(),
+                    dir.join("Named [ccccccccccc].mp4")
+                ),
+                ("aaaaaaaaaaa".to_string(), dir.join("Talk.mp4")),
+                ("aaaaaaaaaaa".to_string(), dir.join("Talk.en.vtt")),
+                ("bbbbbbbbbbb".to_string(), dir.join("Talk.v2.mp4")),
+                ("aaaaaaaaaaa".to_string(), dir.join("Talk.info.json")),
+                ("bbbbbbbbbbb".to_string(), dir.join("Talk.v2.info.json")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use crate::downloads::DownloadContext;
+use crate::ffmpeg::FfmpegSettings;
+use crate::jobs;
+use crate::jobs::JobLog;
+use crate::notes;
+use crate::timestamps::format_timestamp;
+use crate::timestamps::strip_timestamps;
+use crate::timestamps::text_after;
+use crate::timestamps::timed_lines;
+use crate::timestamps::LineKind;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The `chapters` section of the settings file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ChapterSettings {
+    /// The mkvmerge binary, used for Matroska chapters.
+    pub mkvmerge: String,
+}
+
+impl Default for ChapterSettings {
+    fn default() -> Self {
+        ChapterSettings {
+            mkvmerge: "mkvmerge".to_string(),
+        }
+    }
+}
+
+/// How chapters are written into the media file.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterFormat {
+    /// An ffmpeg metadata file, remuxed in with ffmpeg. Keeps the container.
+    #[default]
+    Ffmetadata,
+    /// A Matroska chapters XML file, remuxed in with mkvmerge. Always
+    /// produces an `.mkv`.
+    Matroska,
+}
+
+impl FromStr for ChapterFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "ffmetadata" => Ok(ChapterFormat::Ffmetadata),
+            "matroska" => Ok(ChapterFormat::Matroska),
+            _ => Err(format!(
+                "Unknown chapter format {:?}, expected ffmetadata or matroska",
+                text
+            )),
+        }
+    }
+}
+
+/// Body of `POST /chapters`, and the arguments of the `chapters` command.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ChapterRequest {
+    pub video_id: String,
+    #[serde(default)]
+    pub format: ChapterFormat,
+    /// Replace the catalogued file instead of writing a copy next to it.
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NoteChapter {
+    /// In seconds.
+    pub start: f64,
+    /// In seconds, unknown for the last chapter when the duration is.
+    pub end: Option<f64>,
+    pub title: String,
+}
+
+/// One chapter per line of `content` that mentions a position, titled with
+/// the rest of the line. Timestamp lines from the extension say nothing
+/// themselves, so those take their title from the next line of text. The
+/// play and pause events the extension logs are left out.
+pub fn from_note(content: &str, duration: Option<f64>) -> Vec<NoteChapter> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let mut chapters: Vec<NoteChapter> = Vec::new();
+    for timed in timed_lines(content) {
+        let start = timed.position;
+        let title = match timed.kind {
+            LineKind::Event(_) => continue,
+            LineKind::Timestamp => None,
+            LineKind::Text => Some(line_title(lines[timed.index])),
+        }
+        .filter(|title| !title.is_empty())
+        .or_else(|| {
+            text_after(&lines, timed.index)
+                .into_iter()
+                .map(line_title)
+                .find(|title| !title.is_empty())
+        })
+        .unwrap_or_else(|| format!("Chapter at {}", format_timestamp(start)));
+        // Chapters at the same second would be empty
+        if !chapters
+            .iter()
+            .any(|chapter| chapter.start as u64 == start as u64)
+        {
+            chapters.push(NoteChapter {
+                start,
+                end: None,
+                title,
+            });
+        }
+    }
+    chapters.sort_by(|a, b| a.start.total_cmp(&b.start));
+    let starts = chapters
+        .iter()
+        .skip(1)
+        .map(|chapter| Some(chapter.start))
+        .chain([
+            duration.filter(|duration| chapters.last().is_some_and(|last| *duration > last.start))
+        ])
+        .collect::<Vec<Option<f64>>>();
+    for (chapter, end) in chapters.iter_mut().zip(starts) {
+        chapter.end = end;
+    }
+    chapters
+}
+
+/// The text of a note line minus its timestamps, links and list markers.
+fn line_title(line: &str) -> String {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link =
+        LINK.get_or_init(|| Regex::new(r"\[[^\]]*\]\((?:<[^>]*>|[^)]*)\)|https?://\S+").unwrap());
+    let line = link.replace_all(line, "");
+    strip_timestamps(&line)
+        .trim_matches(|c: char| c.is_whitespace() || "-–*#>:|()[]".contains(c))
+        .to_string()
+}
+
+pub fn to_ffmetadata(chapters: &[NoteChapter]) -> String {
+    let escape = |text: &str| {
+        text.chars().fold(String::new(), |mut escaped, c| {
+            if "=;#\\\n".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            escaped
+        })
+    };
+    let mut text = ";FFMETADATA1\n".to_string();
+    for chapter in chapters {
+        text.push_str("\n[CHAPTER]\nTIMEBASE=1/1000\n");
+        text.push_str(&format!("START={}\n", (chapter.start * 1000.0) as u64));
+        if let Some(end) = chapter.end {
+            text.push_str(&format!("END={}\n", (end * 1000.0) as u64));
+        }
+        text.push_str(&format!("title={}\n", escape(&chapter.title)));
+    }
+    text
+}
+
+pub fn to_matroska_xml(chapters: &[NoteChapter]) -> String {
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let time = |seconds: f64| {
+        let millis = (seconds.max(0.0) * 1000.0) as u64;
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000
+        )
+    };
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                   <!DOCTYPE Chapters SYSTEM \"matroskachapters.dtd\">\n\
+                   <Chapters>\n  <EditionEntry>\n"
+        .to_string();
+    for chapter in chapters {
+        xml.push_str("    <ChapterAtom>\n");
+        xml.push_str(&format!(
+            "      <ChapterTimeStart>{}</ChapterTimeStart>\n",
+            time(chapter.start)
+        ));
+        if let Some(end) = chapter.end {
+            xml.push_str(&format!(
+                "      <ChapterTimeEnd>{}</ChapterTimeEnd>\n",
+                time(end)
+            ));
+        }
+        xml.push_str(&format!(
+            "      <ChapterDisplay>\n        <ChapterString>{}</ChapterString>\n        <ChapterLanguage>und</ChapterLanguage>\n      </ChapterDisplay>\n",
+            escape(&chapter.title)
+        ));
+        xml.push_str("    </ChapterAtom>\n");
+    }
+    xml.push_str("  </EditionEntry>\n</Chapters>\n");
+    xml
+}
+
+/// Turns the timestamps in the video's note into chapters and remuxes them
+/// into its catalogued media file, either as a copy next to it or in its
+/// place. The chapters file is kept next to the media. Returns the file
+/// written, which is recorded in the catalog and the note's metadata.
+pub async fn write(
+    ctx: &DownloadContext,
+    ffmpeg: &FfmpegSettings,
+    settings: &ChapterSettings,
+    duration: Option<f64>,
+    request: &ChapterRequest,
+    log: &JobLog,
+) -> Result<PathBuf, String> {
+    let video_id = request.video_id.as_str();
+    let media = ctx
+        .catalog
+        .lock()
+        .await
+        .get(video_id)
+        .and_then(|entry| entry.media_path())
+        .map(PathBuf::from)
+        .ok_or_else(|| format!("No local copy of {}", video_id))?;
+    let notes_dir = ctx.notes_dir.clone();
+    let id = video_id.to_string();
+    let note = tokio::task::spawn_blocking(move || notes::find_note(&notes_dir, &id))
+        .await
+        .map_err(|e| format!("Note lookup panicked: {}", e))?
+        .ok_or_else(|| format!("No note for {}", video_id))?;
+    let content = tokio::fs::read_to_string(&note)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", note.display(), e))?;
+    let chapters = from_note(&content, duration);
+    if chapters.is_empty() {
+        return Err(format!("No timestamps in {}", note.display()));
+    }
+    log.line(&format!(
+        "{} chapters from {}",
+        chapters.len(),
+        note.display()
+    ))
+    .await?;
+
+    let dir = media.parent().unwrap_or(Path::new("."));
+    let stem = media
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let source_ext = media
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (chapters_file, contents, ext) = match request.format {
+        ChapterFormat::Ffmetadata => (
+            dir.join(format!("{}.chapters.txt", stem)),
+            to_ffmetadata(&chapters),
+            source_ext.as_str(),
+        ),
+        ChapterFormat::Matroska => (
+            dir.join(format!("{}.chapters.xml", stem)),
+            to_matroska_xml(&chapters),
+            "mkv",
+        ),
+    };
+    tokio::fs::write(&chapters_file, contents)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", chapters_file.display(), e))?;
+    log.line(&format!("Wrote {}", chapters_file.display()))
+        .await?;
+
+    // Redoing the chapters of a copy replaces the copy, rather than copying it again
+    let replace = request.replace || stem.ends_with(" (chapters)");
+    let output = if replace {
+        dir.join(format!("{}.chapters-tmp.{}", stem, ext))
+    } else {
+        dir.join(format!("{} (chapters).{}", stem, ext))
+    };
+    let command = match request.format {
+        ChapterFormat::Ffmetadata => {
+            let mut command = ffmpeg.command();
+            command
+                .arg("-i")
+                .arg(&media)
+                .arg("-i")
+                .arg(&chapters_file)
+                .args(["-map", "0", "-map_metadata", "0", "-map_chapters", "1"])
+                .args(["-c", "copy"])
+                .arg(&output);
+            command
+        }
+        ChapterFormat::Matroska => {
+            let mut command = tokio::process::Command::new(&settings.mkvmerge);
+            command
+                .arg("-o")
+                .arg(&output)
+                .arg("--chapters")
+                .arg(&chapters_file)
+                // Drops the chapters the source already has
+                .arg("--no-chapters")
+                .arg(&media);
+            command
+        }
+    };
+    if let Err(err) = jobs::run_command(log, command).await {
+        let _ = tokio::fs::remove_file(&output).await;
+        return Err(err);
+    }
+
+    let written = if replace {
+        let replaced = dir.join(format!("{}.{}", stem, ext));
+        tokio::fs::rename(&output, &replaced)
+            .await
+            .map_err(|e| format!("Failed to replace {}: {}", replaced.display(), e))?;
+        if replaced != media {
+            tokio::fs::remove_file(&media)
+                .await
+                .map_err(|e| format!("Failed to remove {}: {}", media.display(), e))?;
+        }
+        replaced
+    } else {
+        output
+    };
+    log.line(&format!("Wrote {}", written.display())).await?;
+
+    ctx.record(video_id, std::slice::from_ref(&written)).await?;
+    let files = vec![written.clone()];
+    tokio::task::spawn_blocking(move || {
+        let mut meta = notes::NoteMeta::load(&note);
+        meta.record_downloads(&files);
+        meta.save(&note)
+    })
+    .await
+    .map_err(|e| format!("Saving note metadata panicked: {}", e))??;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "\
+Sat Oct 19 2026 05:38:49 GMT-0400 (Eastern Daylight Time) --- 00:00:00 / 00:10:00 (0.00%) --- started
+intro is slow, skip to 1:30
+
+video current time 00:02:05 / 00:10:00 (20.83%) at Sat Oct 19 2026 05:41:02 GMT-0400 (Eastern Daylight Time)
+the actual setup
+Sat Oct 19 2026 05:41:10 GMT-0400 (Eastern Daylight Time) --- 00:02:13 / 00:10:00 (22.17%) --- paused
+Sat Oct 19 2026 05:43:27 GMT-0400 (Eastern Daylight Time) --- 00:02:13 / 00:10:00 (22.17%) --- playing
+Sat Oct 19 2026 05:44:01 GMT-0400 (Eastern Daylight Time) --- 00:02:47 / 00:10:00 (27.83%) --- 👍
+- 7:45 wrap up
+";
+
+    #[test]
+    fn chapters_from_extension_note() {
+        assert_eq!(
+            from_note(NOTE, Some(600.0)),
+            vec![
+                NoteChapter {
+                    start: 90.0,
+                    end: Some(125.0),
+                    title: "intro is slow, skip to".to_string(),
+                },
+                NoteChapter {
+                    start: 125.0,
+                    end: Some(465.0),
+                    title: "the actual setup".to_string(),
+                },
+                NoteChapter {
+                    start: 465.0,
+                    end: Some(600.0),
+                    title: "wrap up".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_are_not_chapters() {
+        let events = NOTE
+            .lines()
+            .filter(|line| line.contains(" --- "))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        assert_eq!(from_note(&events, Some(600.0)), vec![]);
+    }
+
+    #[test]
+    fn clips_are_not_chapters() {
+        let note = "\
+video current time 00:01:05 / 00:10:00 (10.83%) at Sat Oct 19 2026 05:41:02 GMT-0400 (Eastern Daylight Time)
+[✂ 1:05–2:10](</videos/Talk (aaaaaaaaaaa) 1m05.000s-2m10.000s.mp4>)
+the demo
+";
+        assert_eq!(
+            from_note(note, None),
+            vec![NoteChapter {
+                start: 65.0,
+                end: None,
+                title: "the demo".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn titles_leave_out_links() {
+        assert_eq!(
+            line_title("- 3:00 demo, see [the clip](</videos/Talk (x) 3m.mp4>) too"),
+            "demo, see  too"
+        );
+        assert_eq!(
+            line_title("4:00 [slides](https://example.com/a) and https://example.com/b"),
+            "and"
+        );
+    }
+}
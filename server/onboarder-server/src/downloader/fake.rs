@@ -0,0 +1,223 @@
+use super::DownloadRequest;
+use super::Downloader;
+use super::MediaKind;
+use crate::jobs::JobLog;
+use crate::metadata::Chapter;
+use crate::metadata::PlaylistEntry;
+use crate::metadata::PlaylistInfo;
+use crate::metadata::VideoMetadata;
+use crate::settings::Profile;
+use crate::youtube::YoutubeUrl;
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FakeSettings {
+    /// Downloads of these video ids fail halfway through.
+    pub fail_ids: Vec<String>,
+    /// Pause between simulated progress lines.
+    pub step_delay_ms: u64,
+    /// Directory of `<video id>.info.json` files used as metadata in place of
+    /// the made-up defaults, and of `<playlist or channel id>.playlist.json`
+    /// files (flat playlist JSON) used as feeds. They are read on every call,
+    /// so a feed can be edited to simulate new uploads.
+    pub fixtures_dir: Option<PathBuf>,
+    /// Video ids of each playlist or channel the fake knows about, keyed by
+    /// playlist id, channel id or `@handle`.
+    pub playlists: BTreeMap<String, Vec<String>>,
+}
+
+impl Default for FakeSettings {
+    fn default() -> Self {
+        FakeSettings {
+            fail_ids: Vec::new(),
+            step_delay_ms: 200,
+            fixtures_dir: None,
+            playlists: BTreeMap::new(),
+        }
+    }
+}
+
+/// Pretends to be yt-dlp: writes small fixture files and progress lines
+/// without touching the network.
+pub struct FakeDownloader {
+    settings: FakeSettings,
+}
+
+impl FakeDownloader {
+    pub fn new(settings: FakeSettings) -> Self {
+        FakeDownloader { settings }
+    }
+
+    fn filename(&self, metadata: &VideoMetadata, profile: &Profile) -> String {
+        let ext = match profile.kind {
+            MediaKind::Audio => "m4a",
+            MediaKind::Video | MediaKind::Subtitles => "mp4",
+        };
+        profile
+            .output_template
+            .as_deref()
+            .unwrap_or("%(title)s [%(id)s].%(ext)s")
+            .replace("%(title)s", &metadata.title)
+            .replace("%(id)s", &metadata.id)
+            .replace("%(channel)s", metadata.channel_name().unwrap_or_default())
+            .replace("%(ext)s", ext)
+    }
+}
+
+#[async_trait]
+impl Downloader for FakeDownloader {
+    async fn probe_filename(&self, url: &YoutubeUrl, profile: &Profile) -> Result<String, String> {
+        let metadata = self.metadata(url).await?;
+        Ok(self.filename(&metadata, profile))
+    }
+
+    async fn metadata(&self, url: &YoutubeUrl) -> Result<VideoMetadata, String> {
+        let id = url.id();
+        if let Some(fixtures_dir) = &self.settings.fixtures_dir {
+            let fixture = fixtures_dir.join(format!("{}.info.json", id));
+            if fixture.exists() {
+                let content = tokio::fs::read_to_string(&fixture)
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", fixture.display(), e))?;
+                return serde_json::from_str(&content)
+                    .map_err(|e| format!("Failed to parse {}: {}", fixture.display(), e));
+            }
+        }
+        Ok(VideoMetadata {
+            id: id.to_string(),
+            title: format!("Fake video {}", id),
+            channel: Some("Fake Channel".to_string()),
+            channel_id: Some("UCfakefakefakefakefakefake".to_string()),
+            channel_url: Some(
+                "https://www.youtube.com/channel/UCfakefakefakefakefakefake".to_string(),
+            ),
+            uploader: Some("Fake Channel".to_string()),
+            duration: Some(600.0),
+            upload_date: Some("20240101".to_string()),
+            description: Some(
+                "A video that does not exist.\nMore at https://example.com/fake".to_string(),
+            ),
+            chapters: vec![
+                Chapter {
+                    start_time: 0.0,
+                    end_time: 300.0,
+                    title: "Introduction".to_string(),
+                },
+                Chapter {
+                    start_time: 300.0,
+                    end_time: 600.0,
+                    title: "The rest of it".to_string(),
+                },
+            ],
+            tags: vec!["fake".to_string()],
+            webpage_url: Some(url.canonical()),
+            thumbnail: None,
+            view_count: Some(0),
+        })
+    }
+
+    async fn playlist(&self, url: &YoutubeUrl) -> Result<PlaylistInfo, String> {
+        let id = url.id();
+        if let Some(fixtures_dir) = &self.settings.fixtures_dir {
+            let fixture = fixtures_dir.join(format!("{}.playlist.json", id));
+            if fixture.exists() {
+                let content = tokio::fs::read_to_string(&fixture)
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", fixture.display(), e))?;
+                return serde_json::from_str(&content)
+                    .map_err(|e| format!("Failed to parse {}: {}", fixture.display(), e));
+            }
+        }
+        let Some(video_ids) = self.settings.playlists.get(id) else {
+            return Err(format!("Unknown fake playlist {}", id));
+        };
+        let mut entries = Vec::new();
+        for video_id in video_ids {
+            let metadata = self
+                .metadata(&YoutubeUrl::Video {
+                    id: video_id.clone(),
+                })
+                .await?;
+            entries.push(PlaylistEntry {
+                id: metadata.id,
+                title: Some(metadata.title),
+                duration: metadata.duration,
+            });
+        }
+        Ok(PlaylistInfo {
+            id: id.to_string(),
+            title: Some(format!("Fake playlist {}", id)),
+            entries,
+        })
+    }
+
+    async fn download(
+        &self,
+        request: &DownloadRequest,
+        log: &JobLog,
+    ) -> Result<Vec<PathBuf>, String> {
+        let id = request.url.id();
+        let profile = &request.profile;
+        let metadata = self.metadata(&request.url).await?;
+        let filename = self.filename(&metadata, profile);
+        log.line(&format!("[fake] Downloading {} as {}", id, filename))
+            .await?;
+
+        for percent in (0..=100).step_by(10) {
+            tokio::time::sleep(Duration::from_millis(self.settings.step_delay_ms)).await;
+            log.line(&format!("[download] {:5.1}% of 1.00MiB", percent as f64))
+                .await?;
+            if percent == 50 && self.settings.fail_ids.iter().any(|fail| fail == id) {
+                log.line("ERROR: [fake] simulated failure").await?;
+                return Err(format!("simulated failure for {}", id));
+            }
+        }
+
+        let write = |path: PathBuf, content: String| async move {
+            tokio::fs::write(&path, content)
+                .await
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+            Ok::<PathBuf, String>(path)
+        };
+        let main_file = request.dir.join(&filename);
+        let stem = main_file.with_extension("");
+        let mut files = Vec::new();
+        if profile.kind != MediaKind::Subtitles {
+            files.push(write(main_file.clone(), format!("fake media for {}\n", id)).await?);
+        }
+        for lang in &profile.subtitle_langs {
+            let path = PathBuf::from(format!("{}.{}.vtt", stem.display(), lang));
+            files.push(write(path, fake_vtt(&metadata)).await?);
+        }
+        if profile
+            .extra_args
+            .iter()
+            .any(|arg| arg == "--write-info-json")
+        {
+            let path = PathBuf::from(format!("{}.info.json", stem.display()));
+            files.push(write(path, serde_json::to_string(&metadata).unwrap()).await?);
+        }
+        for file in &files {
+            log.line(&format!("[fake] Wrote {}", file.display()))
+                .await?;
+        }
+        Ok(files)
+    }
+}
+
+fn fake_vtt(metadata: &VideoMetadata) -> String {
+    let title = &metadata.title;
+    format!(
+        "WEBVTT\nKind: captions\nLanguage: en\n\n\
+         00:00:00.000 --> 00:00:04.000\nWelcome to {}.\n\n\
+         00:00:04.000 --> 00:00:09.000\nNone of this was ever said out loud.\n\n\
+         00:05:00.000 --> 00:05:06.000\nThat is the end of the introduction.\n",
+        title
+    )
+}
@@ -0,0 +1,89 @@
+mod fake;
+mod ytdlp;
+
+use crate::jobs::JobLog;
+use crate::metadata::PlaylistInfo;
+use crate::metadata::VideoMetadata;
+use crate::settings::Profile;
+use crate::youtube::YoutubeUrl;
+use async_trait::async_trait;
+pub use fake::FakeSettings;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// What a download profile produces.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    #[default]
+    Video,
+    Audio,
+    Subtitles,
+}
+
+/// Which [`Downloader`] the server uses, chosen in the settings file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(tag = "backend", rename_all = "kebab-case")]
+pub enum DownloaderSettings {
+    #[default]
+    YtDlp,
+    Fake(FakeSettings),
+}
+
+pub struct DownloadRequest {
+    pub url: YoutubeUrl,
+    pub profile: Profile,
+    /// Directory the files are written to.
+    pub dir: PathBuf,
+}
+
+/// Everything the server needs from a video site, so it can be swapped for
+/// a fake when there is no network (or no yt-dlp).
+#[async_trait]
+pub trait Downloader: Send + Sync {
+    /// The name the main file of a download would be saved under.
+    async fn probe_filename(&self, url: &YoutubeUrl, profile: &Profile) -> Result<String, String>;
+
+    /// The `--dump-json` style metadata for a video.
+    async fn metadata(&self, url: &YoutubeUrl) -> Result<VideoMetadata, String>;
+
+    /// The entries of a playlist, without fetching each video.
+    async fn playlist(&self, url: &YoutubeUrl) -> Result<PlaylistInfo, String>;
+
+    /// Downloads the video, audio or subtitles (per `request.profile.kind`),
+    /// writing progress to `log`. Returns the files that were written.
+    async fn download(
+        &self,
+        request: &DownloadRequest,
+        log: &JobLog,
+    ) -> Result<Vec<PathBuf>, String>;
+}
+
+pub fn create(settings: &DownloaderSettings) -> Arc<dyn Downloader> {
+    match settings {
+        DownloaderSettings::YtDlp => Arc::new(ytdlp::YtDlp),
+        DownloaderSettings::Fake(settings) => Arc::new(fake::FakeDownloader::new(settings.clone())),
+    }
+}
+
+/// Files in `dir` whose names mention `video_id`, which is how yt-dlp's
+/// default output template (and any sensible custom one) names things.
+pub fn files_for_video(dir: &Path, video_id: &str) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        std::fs::read_dir(dir).map_err(|e| format!("Failed to list {}: {}", dir.display(), e))?;
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.contains(video_id))
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
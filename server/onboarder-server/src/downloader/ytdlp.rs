@@ -0,0 +1,94 @@
+use super::files_for_video;
+use super::DownloadRequest;
+use super::Downloader;
+use crate::jobs;
+use crate::jobs::JobLog;
+use crate::metadata::PlaylistInfo;
+use crate::metadata::VideoMetadata;
+use crate::settings::Profile;
+use crate::youtube::YoutubeUrl;
+use async_trait::async_trait;
+use std::path::PathBuf;
+
+/// The real thing: shells out to the `yt-dlp` binary on the PATH.
+pub struct YtDlp;
+
+#[async_trait]
+impl Downloader for YtDlp {
+    async fn probe_filename(&self, url: &YoutubeUrl, profile: &Profile) -> Result<String, String> {
+        let output = tokio::process::Command::new("yt-dlp")
+            .args(profile.ytdlp_args())
+            .arg("--print")
+            .arg("filename")
+            .arg("--")
+            .arg(url.canonical())
+            .output()
+            .await
+            .map_err(|e| format!("Failed to execute yt-dlp: {}", e))?;
+
+        if output.status.success() {
+            let fname = String::from_utf8_lossy(&output.stdout);
+            let fname_trimmed = fname.trim_end_matches('\n');
+            Ok(fname_trimmed.to_string())
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+
+    async fn metadata(&self, url: &YoutubeUrl) -> Result<VideoMetadata, String> {
+        let output = tokio::process::Command::new("yt-dlp")
+            .arg("--encoding")
+            .arg("utf-8")
+            .arg("--dump-json")
+            .arg("--skip-download")
+            .arg("--no-playlist")
+            .arg("--")
+            .arg(url.canonical())
+            .output()
+            .await
+            .map_err(|e| format!("Failed to execute yt-dlp: {}", e))?;
+
+        if output.status.success() {
+            serde_json::from_slice(&output.stdout)
+                .map_err(|e| format!("Failed to parse yt-dlp output: {}", e))
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+
+    async fn playlist(&self, url: &YoutubeUrl) -> Result<PlaylistInfo, String> {
+        let output = tokio::process::Command::new("yt-dlp")
+            .arg("--encoding")
+            .arg("utf-8")
+            .arg("--flat-playlist")
+            .arg("--dump-single-json")
+            .arg("--")
+            .arg(url.canonical())
+            .output()
+            .await
+            .map_err(|e| format!("Failed to execute yt-dlp: {}", e))?;
+
+        if output.status.success() {
+            serde_json::from_slice(&output.stdout)
+                .map_err(|e| format!("Failed to parse yt-dlp output: {}", e))
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).to_string())
+        }
+    }
+
+    async fn download(
+        &self,
+        request: &DownloadRequest,
+        log: &JobLog,
+    ) -> Result<Vec<PathBuf>, String> {
+        let mut command = tokio::process::Command::new("yt-dlp");
+        command
+            .current_dir(&request.dir)
+            .args(request.profile.ytdlp_args())
+            .arg("--newline")
+            .arg("--")
+            .arg(request.url.canonical());
+        jobs::run_command(log, command).await?;
+        files_for_video(&request.dir, request.url.id())
+    }
+}
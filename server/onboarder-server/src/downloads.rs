@@ -0,0 +1,300 @@
+use crate::catalog::Catalog;
+use crate::catalog::FileKind;
+use crate::downloader::files_for_video;
+use crate::downloader::DownloadRequest;
+use crate::downloader::Downloader;
+use crate::jobs;
+use crate::jobs::JobId;
+use crate::jobs::JobLog;
+use crate::jobs::Jobs;
+use crate::notes;
+use crate::postprocess;
+use crate::settings::Profile;
+use crate::youtube::YoutubeUrl;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::info;
+
+/// The parts of the server state that download jobs need, so they can be
+/// cloned out of it without holding its lock.
+#[derive(Clone)]
+pub struct DownloadContext {
+    pub jobs: Arc<Mutex<Jobs>>,
+    pub downloader: Arc<dyn Downloader>,
+    pub catalog: Arc<Mutex<Catalog>>,
+    pub search_dirs: Vec<PathBuf>,
+    pub notes_dir: PathBuf,
+}
+
+impl DownloadContext {
+    /// Downloads one video, records what it wrote in the catalog, runs the
+    /// profile's post-processing steps on it and links the final files to
+    /// the video's note.
+    pub async fn download_video(
+        &self,
+        request: &DownloadRequest,
+        log: &JobLog,
+    ) -> Result<(), String> {
+        let video_id = request.url.id();
+        let mut files = self.downloader.download(request, log).await?;
+        info!("Downloaded {:?}", files);
+        self.record(video_id, &files).await?;
+
+        let steps = &request.profile.post_process;
+        if !steps.is_empty() {
+            let wanted = FileKind::for_media(request.profile.kind);
+            let main_files = files
+                .iter()
+                .filter(|file| FileKind::from_path(file) == Some(wanted))
+                .cloned()
+                .collect::<Vec<PathBuf>>();
+            let notes_dir = self.notes_dir.clone();
+            let id = video_id.to_string();
+            let note = tokio::task::spawn_blocking(move || notes::find_note(&notes_dir, &id))
+                .await
+                .map_err(|e| format!("Note lookup panicked: {}", e))?;
+            let result = postprocess::run(steps, &main_files, video_id, note.as_deref(), log).await;
+            // Steps may have replaced or added files, so record whatever is there now
+            files = files_for_video(&request.dir, video_id)?;
+            self.record(video_id, &files).await?;
+            result?;
+        }
+
+        let notes_dir = self.notes_dir.clone();
+        let id = video_id.to_string();
+        let linked = tokio::task::spawn_blocking(move || {
+            notes::update_meta(&notes_dir, &id, |meta| meta.record_downloads(&files))
+        })
+        .await
+        .map_err(|e| format!("Note lookup panicked: {}", e))??;
+        match linked {
+            Some(note) => {
+                log.line(&format!("Linked to note {}", note.display()))
+                    .await
+            }
+            None => log.line("No note to link the download to yet").await,
+        }
+    }
+
+    /// Records `files` of the video in the catalog, forgetting ones of that
+    /// video that are gone. Other videos are left to the next rescan.
+    pub async fn record(&self, video_id: &str, files: &[PathBuf]) -> Result<(), String> {
+        let mut catalog = self.catalog.lock().await;
+        catalog.record_files(video_id, files, &self.search_dirs);
+        catalog.forget_missing_of(video_id);
+        catalog.save()
+    }
+
+    /// Whether the catalog already has files of the kind `profile` produces,
+    /// for each of `video_ids`.
+    pub async fn downloaded(&self, video_ids: &[String], profile: &Profile) -> Vec<bool> {
+        let wanted = FileKind::for_media(profile.kind);
+        let catalog = self.catalog.lock().await;
+        video_ids
+            .iter()
+            .map(|id| catalog.get(id).is_some_and(|entry| entry.has_kind(wanted)))
+            .collect()
+    }
+
+    /// Downloads `video_ids` one at a time, each in a child job of `parent`
+    /// whose progress is reported in `log`. Returns the ids that failed.
+    pub async fn download_children(
+        &self,
+        parent: JobId,
+        log: &JobLog,
+        profile_name: &str,
+        profile: &Profile,
+        dir: &Path,
+        video_ids: &[String],
+    ) -> Result<Vec<String>, String> {
+        let kind = format!("download:{}", profile_name);
+        let mut failed = Vec::new();
+        for (index, video_id) in video_ids.iter().enumerate() {
+            let request = DownloadRequest {
+                url: YoutubeUrl::Video {
+                    id: video_id.clone(),
+                },
+                profile: profile.clone(),
+                dir: dir.to_path_buf(),
+            };
+            let child = self
+                .jobs
+                .lock()
+                .await
+                .start_child(parent, &kind, &request.url.canonical());
+            log.line(&format!(
+                "{}/{} Downloading {} in job {}",
+                index + 1,
+                video_ids.len(),
+                video_id,
+                child.id
+            ))
+            .await?;
+            let request = &request;
+            let result = jobs::run(self.jobs.clone(), child, |child_log| async move {
+                self.download_video(request, &child_log).await
+            })
+            .await;
+            if let Err(err) = result {
+                log.line(&format!("{} failed: {}", video_id, err)).await?;
+                failed.push(video_id.clone());
+            }
+        }
+        Ok(failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader;
+    use crate::downloader::DownloaderSettings;
+    use crate::downloader::FakeSettings;
+    use crate::downloader::MediaKind;
+
+    struct Fixture {
+        dir: PathBuf,
+        ctx: DownloadContext,
+    }
+
+    impl Fixture {
+        fn new(name: &str, fail_ids: &[&str]) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "onboarder-downloads-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            for sub_dir in ["fixtures", "downloads", "notes", "logs"] {
+                std::fs::create_dir_all(dir.join(sub_dir)).unwrap();
+            }
+            let downloader = downloader::create(&DownloaderSettings::Fake(FakeSettings {
+                fail_ids: fail_ids.iter().map(|id| id.to_string()).collect(),
+                step_delay_ms: 0,
+                fixtures_dir: Some(dir.join("fixtures")),
+                ..FakeSettings::default()
+            }));
+            let ctx = DownloadContext {
+                jobs: Arc::new(Mutex::new(Jobs::new(dir.join("logs")).unwrap())),
+                downloader,
+                catalog: Arc::new(Mutex::new(Catalog::load(dir.join("catalog.json")).unwrap())),
+                search_dirs: vec![dir.join("downloads")],
+                notes_dir: dir.join("notes"),
+            };
+            Fixture { dir, ctx }
+        }
+
+        fn request(&self, video_id: &str, profile: Profile) -> DownloadRequest {
+            DownloadRequest {
+                url: YoutubeUrl::Video {
+                    id: video_id.to_string(),
+                },
+                profile,
+                dir: self.dir.join("downloads"),
+            }
+        }
+
+        /// Runs `download_video` in a job and returns its outcome and log.
+        async fn download(&self, request: &DownloadRequest) -> (Result<(), String>, String) {
+            let job = self.ctx.jobs.lock().await.start("download", "test");
+            let log_path = job.log_path.clone();
+            let result = jobs::run(self.ctx.jobs.clone(), job, |log| async move {
+                self.ctx.download_video(request, &log).await
+            })
+            .await;
+            (result, std::fs::read_to_string(log_path).unwrap())
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_video_described_by_fixture() {
+        let fixture = Fixture::new("fixture", &[]);
+        std::fs::write(
+            fixture.dir.join("fixtures").join("aaaaaaaaaaa.info.json"),
+            r#"{"id": "aaaaaaaaaaa", "title": "From a fixture", "duration": 90}"#,
+        )
+        .unwrap();
+        let profile = Profile {
+            subtitle_langs: vec!["en".to_string()],
+            extra_args: vec!["--write-info-json".to_string()],
+            ..Profile::default()
+        };
+
+        let (result, log) = fixture
+            .download(&fixture.request("aaaaaaaaaaa", profile))
+            .await;
+        result.unwrap();
+
+        let progress = log
+            .lines()
+            .filter(|line| line.starts_with("[download]"))
+            .collect::<Vec<_>>();
+        assert_eq!(progress.len(), 11);
+        assert_eq!(progress.last(), Some(&"[download] 100.0% of 1.00MiB"));
+        let downloads = fixture.dir.join("downloads");
+        for name in [
+            "From a fixture [aaaaaaaaaaa].mp4",
+            "From a fixture [aaaaaaaaaaa].en.vtt",
+            "From a fixture [aaaaaaaaaaa].info.json",
+        ] {
+            assert!(downloads.join(name).exists(), "{} was not written", name);
+        }
+        let catalog = fixture.ctx.catalog.lock().await;
+        let entry = catalog.get("aaaaaaaaaaa").unwrap();
+        assert!(entry.has_kind(FileKind::Video));
+        assert!(entry.has_kind(FileKind::Subtitle));
+    }
+
+    #[tokio::test]
+    async fn failing_ids_stop_halfway() {
+        let fixture = Fixture::new("fail", &["bbbbbbbbbbb"]);
+        let profile = Profile {
+            kind: MediaKind::Audio,
+            ..Profile::default()
+        };
+
+        let (result, log) = fixture
+            .download(&fixture.request("bbbbbbbbbbb", profile.clone()))
+            .await;
+        assert_eq!(result, Err("simulated failure for bbbbbbbbbbb".to_string()));
+        assert!(log.contains("[download]  50.0% of 1.00MiB"));
+        assert!(!log.contains("[download]  60.0%"));
+        assert!(log.contains("ERROR: [fake] simulated failure"));
+        assert!(fixture
+            .ctx
+            .catalog
+            .lock()
+            .await
+            .get("bbbbbbbbbbb")
+            .is_none());
+
+        let parent = fixture.ctx.jobs.lock().await.start("playlist", "test");
+        let parent_log = JobLog::open(&parent.log_path).await.unwrap();
+        let failed = fixture
+            .ctx
+            .download_children(
+                parent.id,
+                &parent_log,
+                "audio",
+                &profile,
+                &fixture.dir.join("downloads"),
+                &["ccccccccccc".to_string(), "bbbbbbbbbbb".to_string()],
+            )
+            .await
+            .unwrap();
+        assert_eq!(failed, vec!["bbbbbbbbbbb"]);
+        let downloaded = fixture
+            .ctx
+            .downloaded(&["ccccccccccc".to_string()], &profile)
+            .await;
+        assert_eq!(downloaded, vec![true]);
+    }
+}
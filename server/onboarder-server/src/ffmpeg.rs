@@ -0,0 +1,127 @@
+use crate::catalog::FileKind;
+use crate::jobs;
+use crate::jobs::JobLog;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// The `ffmpeg` section of the settings file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FfmpegSettings {
+    /// The ffmpeg binary, looked up on the PATH unless it is a path.
+    pub program: String,
+}
+
+impl Default for FfmpegSettings {
+    fn default() -> Self {
+        FfmpegSettings {
+            program: "ffmpeg".to_string(),
+        }
+    }
+}
+
+impl FfmpegSettings {
+    /// An ffmpeg command that overwrites its output and never waits for input.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(["-hide_banner", "-nostdin", "-y"]);
+        command
+    }
+}
+
+/// Cuts `start..end` (in seconds) out of `source` into `output_stem` plus
+/// the extension that fits how it was cut. Stream copying is tried first as
+/// it is fast and lossless, but it can only cut on keyframes and fails for
+/// some codec and container pairs, so re-encoding is the fallback (or the
+/// only attempt when `reencode` is set). Returns the file written.
+pub async fn clip(
+    settings: &FfmpegSettings,
+    log: &JobLog,
+    source: &Path,
+    start: f64,
+    end: f64,
+    output_stem: &Path,
+    reencode: bool,
+) -> Result<PathBuf, String> {
+    if !reencode {
+        let ext = source
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("mkv");
+        let copied = with_extension(output_stem, ext);
+        let mut command = settings.command();
+        command
+            .arg("-ss")
+            .arg(start.to_string())
+            .arg("-to")
+            .arg(end.to_string())
+            .arg("-i")
+            .arg(source)
+            .args(["-map", "0", "-c", "copy", "-avoid_negative_ts", "make_zero"])
+            .arg(&copied);
+        match jobs::run_command(log, command).await {
+            Ok(()) if is_non_empty(&copied) => return Ok(copied),
+            Ok(()) => log.line("Stream copy produced nothing").await?,
+            Err(err) => log.line(&format!("Stream copy failed: {}", err)).await?,
+        }
+        let _ = tokio::fs::remove_file(&copied).await;
+        log.line("Falling back to re-encoding").await?;
+    }
+
+    let audio_only = FileKind::from_path(source) == Some(FileKind::Audio);
+    let encoded = with_extension(output_stem, if audio_only { "m4a" } else { "mp4" });
+    let mut command = settings.command();
+    command
+        .arg("-ss")
+        .arg(start.to_string())
+        .arg("-to")
+        .arg(end.to_string())
+        .arg("-i")
+        .arg(source);
+    if audio_only {
+        command.args(["-vn", "-c:a", "aac"]);
+    } else {
+        command.args(["-c:v", "libx264", "-preset", "veryfast", "-c:a", "aac"]);
+    }
+    command.arg(&encoded);
+    jobs::run_command(log, command).await?;
+    Ok(encoded)
+}
+
+/// Appends `ext`, where [`Path::with_extension`] would replace anything
+/// after a dot in the name.
+fn with_extension(stem: &Path, ext: &str) -> PathBuf {
+    PathBuf::from(format!("{}.{}", stem.display(), ext))
+}
+
+fn is_non_empty(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0)
+}
+
+/// Writes the frame of `source` at `at` seconds to `output` as an image in
+/// the format its extension names.
+pub async fn snapshot(
+    settings: &FfmpegSettings,
+    log: &JobLog,
+    source: &Path,
+    at: f64,
+    output: &Path,
+) -> Result<(), String> {
+    let mut command = settings.command();
+    command
+        .arg("-ss")
+        .arg(at.to_string())
+        .arg("-i")
+        .arg(source)
+        .args(["-frames:v", "1", "-q:v", "2"])
+        .arg(output);
+    jobs::run_command(log, command).await?;
+    if is_non_empty(output) {
+        Ok(())
+    } else {
+        Err(format!("No frame at {}s", at))
+    }
+}
@@ -0,0 +1,346 @@
+use globset::GlobBuilder;
+use globset::GlobSet;
+use globset::GlobSetBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// How `search_dirs` are walked, from the `search` section of the settings file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SearchSettings {
+    /// Globs matched against paths relative to the search dir. When
+    /// non-empty, only matching files are considered.
+    pub include: Vec<String>,
+    /// Globs matched against paths relative to the search dir. Matching
+    /// files and directories are skipped.
+    pub exclude: Vec<String>,
+    pub follow_symlinks: bool,
+    /// How many directories deep to go below each search dir, unlimited if unset.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings {
+            include: Vec::new(),
+            exclude: vec!["*.part".to_string(), "*.ytdl".to_string()],
+            follow_symlinks: false,
+            max_depth: None,
+        }
+    }
+}
+
+struct CachedDir {
+    modified: SystemTime,
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+}
+
+/// Recursive, case-insensitive file search. Directory listings are cached
+/// and reused for as long as the directory's mtime stays the same, which is
+/// what changes when entries are added, removed or renamed in it.
+pub struct FileSearch {
+    settings: SearchSettings,
+    include: GlobSet,
+    exclude: GlobSet,
+    cache: HashMap<PathBuf, CachedDir>,
+}
+
+impl FileSearch {
+    pub fn new(settings: &SearchSettings) -> Result<Self, String> {
+        Ok(FileSearch {
+            settings: settings.clone(),
+            include: build_globs(&settings.include)?,
+            exclude: build_globs(&settings.exclude)?,
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Files under `root` whose full path contains `text`, ignoring case.
+    pub fn search(&mut self, text: &str, root: &Path) -> Result<Vec<PathBuf>, String> {
+        let needle = text.to_lowercase();
+        Ok(self
+            .files(root)?
+            .into_iter()
+            .filter(|path| path.to_string_lossy().to_lowercase().contains(&needle))
+            .collect())
+    }
+
+    /// Every file under `root` that passes the include and exclude filters.
+    pub fn files(&mut self, root: &Path) -> Result<Vec<PathBuf>, String> {
+        let mut files = Vec::new();
+        let mut visited = HashSet::new();
+        // Only the root itself being unreadable is an error, anything below it is skipped
+        self.list(root)
+            .map_err(|e| format!("Failed to list {}: {}", root.display(), e))?;
+        self.walk(root, root, 0, &mut visited, &mut files);
+        Ok(files)
+    }
+
+    fn walk(
+        &mut self,
+        root: &Path,
+        dir: &Path,
+        depth: usize,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+    ) {
+        // Guards against symlink loops
+        if let Ok(canonical) = dir.canonicalize() {
+            if !visited.insert(canonical) {
+                return;
+            }
+        }
+        let (dir_files, dirs) = match self.list(dir) {
+            Ok(it) => it,
+            Err(err) => {
+                warn!("Failed to list {}: {}", dir.display(), err);
+                return;
+            }
+        };
+        for path in dir_files {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if self.exclude.is_match(relative) {
+                continue;
+            }
+            if self.settings.include.is_empty() || self.include.is_match(relative) {
+                files.push(path);
+            }
+        }
+        if self.settings.max_depth.is_some_and(|max| depth >= max) {
+            return;
+        }
+        for path in dirs {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            if !self.exclude.is_match(relative) {
+                self.walk(root, &path, depth + 1, visited, files);
+            }
+        }
+    }
+
+    fn list(&mut self, dir: &Path) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+        let modified = std::fs::metadata(dir)?.modified()?;
+        if let Some(cached) = self.cache.get(dir) {
+            if cached.modified == modified {
+                return Ok((cached.files.clone(), cached.dirs.clone()));
+            }
+        }
+
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(dir)?.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            let Ok(mut file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_symlink() {
+                if !self.settings.follow_symlinks {
+                    continue;
+                }
+                match std::fs::metadata(&path) {
+                    Ok(target) => file_type = target.file_type(),
+                    Err(_) => continue,
+                }
+            }
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+        dirs.sort();
+        self.cache.insert(
+            dir.to_path_buf(),
+            CachedDir {
+                modified,
+                files: files.clone(),
+                dirs: dirs.clone(),
+            },
+        );
+        Ok((files, dirs))
+    }
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Invalid glob {:?}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("Invalid globs {:?}: {}", patterns, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Tree {
+        root: PathBuf,
+    }
+
+    impl Tree {
+        fn new(name: &str, files: &[&str]) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "onboarder-file-search-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&root);
+            std::fs::create_dir_all(&root).unwrap();
+            let tree = Tree { root };
+            for file in files {
+                tree.write(file);
+            }
+            tree
+        }
+
+        fn write(&self, file: &str) {
+            let path = self.root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        /// `files` relative to the root, as `/`-separated strings.
+        fn relative(&self, files: Vec<PathBuf>) -> Vec<String> {
+            let mut relative = files
+                .iter()
+                .map(|path| {
+                    let relative = path.strip_prefix(&self.root).unwrap();
+                    relative.to_string_lossy().replace('\\', "/")
+                })
+                .collect::<Vec<_>>();
+            relative.sort();
+            relative
+        }
+
+        fn files(&self, settings: SearchSettings) -> Vec<String> {
+            let mut file_search = FileSearch::new(&settings).unwrap();
+            self.relative(file_search.files(&self.root).unwrap())
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn include_and_exclude_globs() {
+        let tree = Tree::new(
+            "globs",
+            &[
+                "Talk.mp4",
+                "Talk.mp4.part",
+                "Talk.EN.vtt",
+                "notes.txt",
+                "trash/Old.mp4",
+                "deep/er/Song.m4a",
+            ],
+        );
+
+        assert_eq!(
+            tree.files(SearchSettings::default()),
+            vec![
+                "Talk.EN.vtt",
+                "Talk.mp4",
+                "deep/er/Song.m4a",
+                "notes.txt",
+                "trash/Old.mp4"
+            ]
+        );
+        assert_eq!(
+            tree.files(SearchSettings {
+                include: vec!["**/*.mp4".to_string(), "**/*.m4a".to_string()],
+                exclude: vec!["trash".to_string(), "*.part".to_string()],
+                ..SearchSettings::default()
+            }),
+            vec!["Talk.mp4", "deep/er/Song.m4a"]
+        );
+        // Globs ignore case like the search itself
+        assert_eq!(
+            tree.files(SearchSettings {
+                include: vec!["*.en.vtt".to_string()],
+                ..SearchSettings::default()
+            }),
+            vec!["Talk.EN.vtt"]
+        );
+        assert!(FileSearch::new(&SearchSettings {
+            include: vec!["[".to_string()],
+            ..SearchSettings::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn max_depth_counts_directories_below_the_root() {
+        let tree = Tree::new("depth", &["a.mp4", "one/b.mp4", "one/two/c.mp4"]);
+
+        let depth = |max_depth| {
+            tree.files(SearchSettings {
+                max_depth,
+                ..SearchSettings::default()
+            })
+        };
+        assert_eq!(depth(Some(0)), vec!["a.mp4"]);
+        assert_eq!(depth(Some(1)), vec!["a.mp4", "one/b.mp4"]);
+        assert_eq!(depth(None), vec!["a.mp4", "one/b.mp4", "one/two/c.mp4"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_loops_are_walked_once() {
+        let tree = Tree::new("symlinks", &["one/a.mp4"]);
+        std::os::unix::fs::symlink(&tree.root, tree.root.join("one/loop")).unwrap();
+        std::os::unix::fs::symlink(tree.root.join("one/a.mp4"), tree.root.join("b.mp4")).unwrap();
+
+        assert_eq!(tree.files(SearchSettings::default()), vec!["one/a.mp4"]);
+        assert_eq!(
+            tree.files(SearchSettings {
+                follow_symlinks: true,
+                ..SearchSettings::default()
+            }),
+            vec!["b.mp4", "one/a.mp4"]
+        );
+    }
+
+    #[test]
+    fn listings_are_cached_until_the_directory_changes() {
+        let tree = Tree::new("cache", &["Talk.mp4"]);
+        let mut file_search = FileSearch::new(&SearchSettings::default()).unwrap();
+        let search = |file_search: &mut FileSearch, text| {
+            tree.relative(file_search.search(text, &tree.root).unwrap())
+        };
+        assert_eq!(search(&mut file_search, "TALK"), vec!["Talk.mp4"]);
+
+        // Adding a file while keeping the old mtime leaves the cached listing in use
+        let modified = std::fs::metadata(&tree.root).unwrap().modified().unwrap();
+        tree.write("Talk.en.vtt");
+        let set_modified = |time| {
+            std::fs::File::open(&tree.root)
+                .unwrap()
+                .set_modified(time)
+                .unwrap()
+        };
+        set_modified(modified);
+        assert_eq!(search(&mut file_search, "talk"), vec!["Talk.mp4"]);
+
+        set_modified(modified + std::time::Duration::from_secs(1));
+        assert_eq!(
+            search(&mut file_search, "talk"),
+            vec!["Talk.en.vtt", "Talk.mp4"]
+        );
+    }
+}
@@ -0,0 +1,501 @@
+use chrono::DateTime;
+use chrono::Local;
+use hyper::Body;
+use hyper::Response;
+use hyper::StatusCode;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+pub type JobId = u64;
+
+/// Once a log grows past this size it is rotated to `job-<id>.log.1`.
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+/// How many rotated files (`.1`, `.2`, ...) are kept per job.
+const LOG_ROTATIONS: usize = 3;
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub kind: String,
+    pub target: String,
+    pub status: JobStatus,
+    pub started: DateTime<Local>,
+    pub finished: Option<DateTime<Local>>,
+    pub error: Option<String>,
+    /// Set on jobs started on behalf of another job, such as the entries of
+    /// a playlist download.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<JobId>,
+    /// Set on jobs that run child jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    /// What the job made, for the extension to put in the note it shows.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub note_lines: Vec<NoteLine>,
+    #[serde(skip)]
+    pub log_path: PathBuf,
+}
+
+/// A line for the note of the job's video. The server doesn't write notes
+/// itself, as the extension saves its whole text box on every change and
+/// would undo it, so the extension adds these once the job has succeeded.
+#[derive(Serialize, Clone, Debug)]
+pub struct NoteLine {
+    pub line: String,
+    /// The note line to put it after, or the end of the note if it is gone.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+}
+
+/// How far along the children of a job are.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Progress {
+    /// Everything the job set out to do, including skipped items.
+    pub total: usize,
+    /// Items that needed no child job.
+    pub skipped: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub children: Vec<JobId>,
+}
+
+/// Body returned by the endpoints that kick off a job.
+#[derive(Serialize, Debug)]
+pub struct JobStarted {
+    pub job_id: JobId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    pub log_url: String,
+}
+
+impl JobStarted {
+    pub fn new(job: &Job, filename: Option<String>) -> Self {
+        JobStarted {
+            job_id: job.id,
+            filename,
+            log_url: format!("/jobs/{}/log", job.id),
+        }
+    }
+}
+
+pub struct Jobs {
+    logs_dir: PathBuf,
+    next_id: JobId,
+    jobs: BTreeMap<JobId, Job>,
+}
+
+impl Jobs {
+    /// Jobs from previous runs are not remembered, but their logs are, so ids
+    /// continue after the highest id found in `logs_dir`.
+    pub fn new(logs_dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&logs_dir)?;
+        let mut highest = 0;
+        for entry in std::fs::read_dir(&logs_dir)? {
+            let name = entry?.file_name();
+            let id = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("job-"))
+                .and_then(|rest| rest.split('.').next())
+                .and_then(|id| id.parse::<JobId>().ok());
+            if let Some(id) = id {
+                highest = highest.max(id);
+            }
+        }
+        Ok(Jobs {
+            logs_dir,
+            next_id: highest + 1,
+            jobs: BTreeMap::new(),
+        })
+    }
+
+    pub fn start(&mut self, kind: &str, target: &str) -> Job {
+        self.start_with_parent(kind, target, None)
+    }
+
+    /// Starts a job that counts towards the progress of `parent`.
+    pub fn start_child(&mut self, parent: JobId, kind: &str, target: &str) -> Job {
+        let job = self.start_with_parent(kind, target, Some(parent));
+        if let Some(progress) = self.progress_mut(parent) {
+            progress.children.push(job.id);
+        }
+        job
+    }
+
+    fn start_with_parent(&mut self, kind: &str, target: &str, parent: Option<JobId>) -> Job {
+        let id = self.next_id;
+        self.next_id += 1;
+        let job = Job {
+            id,
+            kind: kind.to_string(),
+            target: target.to_string(),
+            status: JobStatus::Running,
+            started: Local::now(),
+            finished: None,
+            error: None,
+            parent,
+            progress: None,
+            note_lines: Vec::new(),
+            log_path: self.log_path(id),
+        };
+        info!("Starting job {} ({}) for {}", id, kind, target);
+        self.jobs.insert(id, job.clone());
+        job
+    }
+
+    pub fn finish(&mut self, id: JobId, result: Result<(), String>) {
+        let Some(job) = self.jobs.get_mut(&id) else {
+            warn!("Tried to finish unknown job {}", id);
+            return;
+        };
+        job.finished = Some(Local::now());
+        let succeeded = result.is_ok();
+        match result {
+            Ok(()) => {
+                info!("Job {} succeeded", id);
+                job.status = JobStatus::Succeeded;
+            }
+            Err(err) => {
+                error!("Job {} failed: {}", id, err);
+                job.status = JobStatus::Failed;
+                job.error = Some(err);
+            }
+        }
+        if let Some(progress) = job.parent.and_then(|parent| self.progress_mut(parent)) {
+            if succeeded {
+                progress.succeeded += 1;
+            } else {
+                progress.failed += 1;
+            }
+        }
+    }
+
+    /// Marks `id` as a job with `total` items, `skipped` of which need no child job.
+    pub fn set_progress(&mut self, id: JobId, total: usize, skipped: usize) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.progress = Some(Progress {
+                total,
+                skipped,
+                ..Default::default()
+            });
+        }
+    }
+
+    pub fn add_note_line(&mut self, id: JobId, line: NoteLine) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.note_lines.push(line);
+        }
+    }
+
+    fn progress_mut(&mut self, id: JobId) -> Option<&mut Progress> {
+        self.jobs.get_mut(&id)?.progress.as_mut()
+    }
+
+    pub fn get(&self, id: JobId) -> Option<&Job> {
+        self.jobs.get(&id)
+    }
+
+    pub fn list(&self) -> Vec<&Job> {
+        self.jobs.values().rev().collect()
+    }
+
+    pub fn log_path(&self, id: JobId) -> PathBuf {
+        self.logs_dir.join(format!("job-{}.log", id))
+    }
+}
+
+/// Handle to a job's log file, which rotates itself once it reaches
+/// [`MAX_LOG_BYTES`]. Clones write to the same file.
+#[derive(Clone)]
+pub struct JobLog {
+    inner: Arc<Mutex<LogFile>>,
+}
+
+struct LogFile {
+    path: PathBuf,
+    file: tokio::fs::File,
+    written: u64,
+}
+
+impl JobLog {
+    pub async fn open(path: &Path) -> std::io::Result<Self> {
+        Ok(JobLog {
+            inner: Arc::new(Mutex::new(LogFile::open(path).await?)),
+        })
+    }
+
+    pub async fn line(&self, line: &str) -> Result<(), String> {
+        self.inner
+            .lock()
+            .await
+            .write_line(line)
+            .await
+            .map_err(|e| format!("Failed to write to job log: {}", e))
+    }
+}
+
+impl LogFile {
+    async fn open(path: &Path) -> std::io::Result<Self> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let written = file.metadata().await?.len();
+        Ok(LogFile {
+            path: path.to_path_buf(),
+            file,
+            written,
+        })
+    }
+
+    async fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.written >= MAX_LOG_BYTES {
+            self.rotate().await?;
+        }
+        let line = format!("{}\n", line);
+        self.file.write_all(line.as_bytes()).await?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        let rotated = |n: usize| PathBuf::from(format!("{}.{}", self.path.display(), n));
+        for n in (1..LOG_ROTATIONS).rev() {
+            if rotated(n).exists() {
+                tokio::fs::rename(rotated(n), rotated(n + 1)).await?;
+            }
+        }
+        tokio::fs::rename(&self.path, rotated(1)).await?;
+        let path = self.path.clone();
+        *self = LogFile::open(&path).await?;
+        Ok(())
+    }
+}
+
+/// Registers a new job and runs `work` for it in the background, recording
+/// its outcome once it finishes.
+pub async fn spawn<F, Fut>(jobs: Arc<Mutex<Jobs>>, kind: &str, target: &str, work: F) -> Job
+where
+    F: FnOnce(JobLog) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    let job = jobs.lock().await.start(kind, target);
+    tokio::spawn(run(jobs, job.clone(), work));
+    job
+}
+
+/// Runs `work` for an already started job and records its outcome, which
+/// is also returned.
+pub async fn run<F, Fut>(jobs: Arc<Mutex<Jobs>>, job: Job, work: F) -> Result<(), String>
+where
+    F: FnOnce(JobLog) -> Fut,
+    Fut: Future<Output = Result<(), String>>,
+{
+    let result = match JobLog::open(&job.log_path).await {
+        Ok(log) => {
+            let result = work(log.clone()).await;
+            if let Err(err) = &result {
+                if let Err(e) = log.line(&format!("error: {}", err)).await {
+                    error!("{}", e);
+                }
+            }
+            result
+        }
+        Err(err) => Err(format!("Failed to open job log: {}", err)),
+    };
+    jobs.lock().await.finish(job.id, result.clone());
+    result
+}
+
+/// Opens a window following the log at `log_path`. The job itself keeps
+/// running in the server either way; this is only a view onto it.
+pub fn open_console(console_command: &[String], log_path: &Path) -> Result<(), String> {
+    let Some((program, args)) = console_command.split_first() else {
+        return Err("No console command configured".to_string());
+    };
+    // Make sure there is something to follow before the job has written anything
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .map_err(|e| format!("Failed to create job log: {}", e))?;
+    let log = log_path.display().to_string();
+    std::process::Command::new(program)
+        .args(args.iter().map(|arg| arg.replace("{log}", &log)))
+        .spawn()
+        .map_err(|e| format!("Failed to open console with {:?}: {}", program, e))?;
+    Ok(())
+}
+
+/// Runs `command` to completion, capturing its stdout and stderr into `log`.
+pub async fn run_command(log: &JobLog, mut command: Command) -> Result<(), String> {
+    log.line(&format!("$ {:?}", command.as_std())).await?;
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("Failed to execute command: {}", e))?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    if let Some(stdout) = child.stdout.take() {
+        forward_lines(stdout, "", tx.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward_lines(stderr, "[stderr] ", tx);
+    }
+    while let Some(line) = rx.recv().await {
+        log.line(&line).await?;
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| format!("Failed to wait for command: {}", e))?;
+    log.line(&format!("exited with {}", status)).await?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("command exited with {}", status))
+    }
+}
+
+/// Progress bars redraw with `\r`, so those count as line breaks too.
+fn forward_lines(
+    stream: impl AsyncRead + Unpin + Send + 'static,
+    prefix: &'static str,
+    tx: mpsc::UnboundedSender<String>,
+) {
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(stream);
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            match reader.read_until(b'\n', &mut buf).await {
+                Ok(0) => break,
+                Ok(_) => {
+                    let text = String::from_utf8_lossy(&buf);
+                    for line in text.split(['\r', '\n']).filter(|line| !line.is_empty()) {
+                        if tx.send(format!("{}{}", prefix, line)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(format!("{}<failed to read output: {}>", prefix, err));
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Serves `GET /jobs`, `GET /jobs/{id}` and `GET /jobs/{id}/log?tail=N`.
+pub async fn handle_get(path: &str, query: Option<&str>, jobs: &Mutex<Jobs>) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(query.unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let segments = path
+        .trim_matches('/')
+        .split('/')
+        .skip(1)
+        .collect::<Vec<&str>>();
+
+    let jobs = jobs.lock().await;
+    let id = match segments.first().map(|id| id.parse::<JobId>()) {
+        None => {
+            return Response::new(serde_json::to_string(&jobs.list()).unwrap().into());
+        }
+        Some(Ok(id)) => id,
+        Some(Err(_)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Invalid job id".into())
+                .unwrap();
+        }
+    };
+
+    match segments.get(1..).unwrap_or_default() {
+        [] => match jobs.get(id) {
+            Some(job) => Response::new(serde_json::to_string(job).unwrap().into()),
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("No such job".into())
+                .unwrap(),
+        },
+        ["log"] => {
+            let tail = match query_map.get("tail").map(|tail| tail.parse::<usize>()) {
+                None => None,
+                Some(Ok(tail)) => Some(tail),
+                Some(Err(_)) => {
+                    return Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body("Invalid tail parameter".into())
+                        .unwrap();
+                }
+            };
+            let log_path = jobs.log_path(id);
+            drop(jobs);
+            match tokio::fs::read(&log_path).await {
+                Ok(bytes) => {
+                    let content = String::from_utf8_lossy(&bytes);
+                    let body = match tail {
+                        Some(tail) => {
+                            let lines = content.lines().collect::<Vec<&str>>();
+                            let mut tailed = lines[lines.len().saturating_sub(tail)..].join("\n");
+                            tailed.push('\n');
+                            tailed
+                        }
+                        None => content.into_owned(),
+                    };
+                    Response::builder()
+                        .header("Content-Type", "text/plain; charset=utf-8")
+                        .body(body.into())
+                        .unwrap()
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("No log for job".into())
+                    .unwrap(),
+                Err(err) => {
+                    error!("Error reading job log {}: {}", log_path.display(), err);
+                    Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body("Error reading job log".into())
+                        .unwrap()
+                }
+            }
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("".into())
+            .unwrap(),
+    }
+}
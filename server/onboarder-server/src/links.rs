@@ -0,0 +1,100 @@
+use crate::youtube;
+use crate::youtube::YoutubeUrl;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+/// Lines longer than this are cut short in [`NoteLink::context`].
+const MAX_CONTEXT_CHARS: usize = 200;
+
+/// What a link in a note points at. Links are resolved to notes when they
+/// are followed, so a link can be written before the note it points at.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "target", rename_all = "lowercase")]
+pub enum LinkTarget {
+    /// `[[title]]`, matching a note's title, its whole id or its video id.
+    Title(String),
+    /// A pasted YouTube video URL, matching the note about that video.
+    Video(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteLink {
+    #[serde(flatten)]
+    pub target: LinkTarget,
+    /// The line of the note the link is on.
+    pub context: String,
+}
+
+fn wiki_link_regex() -> &'static Regex {
+    static WIKI_LINK: OnceLock<Regex> = OnceLock::new();
+    // `[[title]]` or `[[title|shown text]]`
+    WIKI_LINK.get_or_init(|| Regex::new(r"\[\[([^\[\]|\n]+)(?:\|[^\[\]\n]*)?\]\]").unwrap())
+}
+
+fn url_regex() -> &'static Regex {
+    static URL: OnceLock<Regex> = OnceLock::new();
+    // Stops at brackets so Markdown links and `<url>` work
+    URL.get_or_init(|| Regex::new(r"https?://[^\s<>()\[\]]+").unwrap())
+}
+
+fn video_id(url: &str) -> Option<String> {
+    let url = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"']);
+    match youtube::parse_url(url) {
+        Ok(YoutubeUrl::Video { id }) => Some(id),
+        _ => None,
+    }
+}
+
+/// Titles are compared ignoring case and runs of whitespace.
+pub fn normalize_title(title: &str) -> String {
+    title
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn context(line: &str) -> String {
+    let line = line.trim();
+    match line.char_indices().nth(MAX_CONTEXT_CHARS) {
+        Some((end, _)) => format!("{}…", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// The links in a note, each target once, in the order they first appear.
+/// Lines that are one of `skipped` are passed over, so a link there doesn't
+/// hide the same link written again elsewhere.
+pub fn extract(content: &str, skipped: &[String]) -> Vec<NoteLink> {
+    let mut seen = HashSet::new();
+    let mut links = Vec::new();
+    for line in content.lines() {
+        if skipped.iter().any(|skipped| skipped.trim() == line.trim()) {
+            continue;
+        }
+        let wiki_links = wiki_link_regex().captures_iter(line).map(|captures| {
+            let target = captures[1].trim();
+            match video_id(target) {
+                Some(id) => LinkTarget::Video(id),
+                None => LinkTarget::Title(target.to_string()),
+            }
+        });
+        let urls = url_regex()
+            .find_iter(line)
+            .filter_map(|url| video_id(url.as_str()))
+            .map(LinkTarget::Video);
+        for target in wiki_links.chain(urls).collect::<Vec<LinkTarget>>() {
+            let empty = matches!(&target, LinkTarget::Title(title) if title.is_empty());
+            if !empty && seen.insert(target.clone()) {
+                links.push(NoteLink {
+                    target,
+                    context: context(line),
+                });
+            }
+        }
+    }
+    links
+}
@@ -1,8 +1,44 @@
+mod annotated;
+mod catalog;
+mod chapters;
+mod downloader;
+mod downloads;
+mod ffmpeg;
+mod file_search;
+mod jobs;
+mod links;
+mod media;
+mod metadata;
+mod note_template;
+mod notes;
+mod notes_index;
+mod overlay;
+mod postprocess;
+mod ranking;
+mod settings;
+mod snapshots;
+mod subscriptions;
+mod subtitles;
+mod tags;
+mod timestamps;
+mod transcript_index;
+mod youtube;
+
+use catalog::Catalog;
+use catalog::FileKind;
+use catalog::FileMatch;
+use chapters::ChapterFormat;
+use chapters::ChapterRequest;
+use chrono::DateTime;
 use chrono::Datelike;
 use chrono::Local;
 use cloud_terrastodon_core_user_input::prelude::pick;
 use cloud_terrastodon_core_user_input::prelude::prompt_line;
 use cloud_terrastodon_core_user_input::prelude::FzfArgs;
+use downloader::DownloadRequest;
+use downloader::Downloader;
+use downloads::DownloadContext;
+use file_search::FileSearch;
 use hyper::server::conn::AddrIncoming;
 use hyper::service::make_service_fn;
 use hyper::service::service_fn;
@@ -14,9 +50,17 @@ use hyper::Server;
 use hyper::StatusCode;
 use hyper_rustls::TlsAcceptor;
 use itertools::Itertools;
+use jobs::JobStarted;
+use jobs::Jobs;
+use metadata::MetadataCache;
+use notes_index::IndexedNote;
+use notes_index::NotesIndex;
+use overlay::OverlayRequest;
 use serde::Deserialize;
 use serde::Serialize;
+use settings::Settings;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::Infallible;
 use std::env;
 use std::fs::create_dir_all;
@@ -28,13 +72,18 @@ use std::sync::Arc;
 use structopt::StructOpt;
 use strum::Display;
 use strum::VariantArray;
+use subscriptions::Subscriptions;
+use timestamps::Position;
+use tokio::sync::mpsc;
 use tokio::sync::Mutex;
-use tracing::debug;
 use tracing::error;
 use tracing::info;
 use tracing::level_filters::LevelFilter;
 use tracing::trace;
+use tracing::warn;
 use tracing_subscriber::EnvFilter;
+use transcript_index::TranscriptIndex;
+use youtube::YoutubeUrl;
 
 #[derive(StructOpt)]
 struct Config {
@@ -46,6 +95,28 @@ struct Config {
     search_dirs: Vec<std::path::PathBuf>,
     #[structopt(short, long, parse(try_from_str))]
     port: usize,
+    #[structopt(long, parse(from_os_str), default_value = "data")]
+    data_dir: std::path::PathBuf,
+    #[structopt(long, parse(from_os_str), default_value = "settings.json")]
+    settings: std::path::PathBuf,
+    /// Runs one task with the same settings and data instead of serving.
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Clone)]
+enum Command {
+    /// Writes the timestamps in a video's note into its downloaded file as
+    /// chapters, like `POST /chapters`.
+    Chapters {
+        video_id: String,
+        /// `ffmetadata` (remuxed with ffmpeg) or `matroska` (with mkvmerge).
+        #[structopt(long, default_value = "ffmetadata")]
+        format: ChapterFormat,
+        /// Replace the file instead of writing a copy next to it.
+        #[structopt(long)]
+        replace: bool,
+    },
 }
 impl Clone for Config {
     fn clone(&self) -> Self {
@@ -54,6 +125,9 @@ impl Clone for Config {
             downloads_dir: self.downloads_dir.clone(),
             search_dirs: self.search_dirs.clone(),
             port: self.port.clone(),
+            data_dir: self.data_dir.clone(),
+            settings: self.settings.clone(),
+            command: self.command.clone(),
         }
     }
 }
@@ -89,6 +163,17 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     if !config.downloads_dir.exists() {
         create_dir_all(&config.downloads_dir).unwrap();
     }
+    if !config.data_dir.exists() {
+        create_dir_all(&config.data_dir).unwrap();
+    }
+
+    if let Some(command) = config.command.clone() {
+        if let Err(e) = run_command(config, command) {
+            error!("FAILED: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // Serve an echo service over HTTPS, with proper error handling.
     if let Err(e) = run_server(config) {
@@ -102,11 +187,897 @@ fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 struct Note {
     id: String,
     content: String,
+    /// Where the server keeps metadata for the note's video, if it has one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata_url: Option<String>,
+    /// The local video (or audio) for the note, from the note's metadata or
+    /// failing that the catalog.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    media_path: Option<PathBuf>,
+    /// Clips cut from the note's video with `/clip`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    clips: Vec<PathBuf>,
+    /// From [`notes::version`]. Sent back with `/set_note`, it makes the save
+    /// fail if the note was changed in the meantime.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+}
+
+/// Response of `/set_note`.
+#[derive(Serialize, Debug)]
+struct NoteSaved {
+    version: String,
+}
+
+/// Body of `POST /clip`.
+#[derive(Deserialize, Debug)]
+struct ClipRequest {
+    video_id: String,
+    start: Position,
+    end: Position,
+    /// Skip straight to re-encoding, for cuts that must be frame accurate.
+    #[serde(default)]
+    reencode: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct ExistsResponse {
+    search: String,
+    matches: Vec<FileMatch>,
+    /// Search dirs that could not be searched.
+    errors: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct OverlayResponse {
+    path: PathBuf,
+    cues: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct TranscriptResponse {
+    video_id: String,
+    path: PathBuf,
+    language: Option<String>,
+    /// Languages of every subtitle file there is for the video.
+    languages: Vec<String>,
+    segments: Vec<subtitles::Segment>,
+}
+
+#[derive(Serialize, Debug)]
+struct NoteSummary {
+    id: String,
+    title: String,
+    video_id: Option<String>,
+    channel: Option<String>,
+    date: Option<chrono::NaiveDate>,
+    created: Option<DateTime<Local>>,
+    modified: Option<DateTime<Local>>,
+    size: u64,
+    has_media: bool,
+    has_subtitles: bool,
+}
+
+impl NoteSummary {
+    fn new(note: IndexedNote, catalog: &Catalog) -> Self {
+        let entry = note.video_id.as_ref().and_then(|id| catalog.get(id));
+        NoteSummary {
+            title: notes::title_from_note_id(&note.id).to_string(),
+            has_media: entry
+                .and_then(|entry| entry.media_path())
+                .is_some_and(|path| path.exists()),
+            has_subtitles: entry
+                .is_some_and(|entry| !subtitles::transcript_files(entry).is_empty()),
+            id: note.id,
+            video_id: note.video_id,
+            channel: note.channel,
+            date: note.date,
+            created: note.created,
+            modified: note.modified,
+            size: note.size,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct NoteListResponse {
+    /// Notes matching the filter, of which `notes` is one page.
+    total: usize,
+    offset: usize,
+    limit: usize,
+    notes: Vec<NoteSummary>,
+}
+
+#[derive(Serialize, Debug)]
+struct Backlink {
+    #[serde(flatten)]
+    note: NoteSummary,
+    /// The link in that note, with the line it is on.
+    link: links::NoteLink,
+}
+
+#[derive(Serialize, Debug)]
+struct BacklinksResponse {
+    id: String,
+    title: String,
+    /// The notes mentioning this one, newest first.
+    backlinks: Vec<Backlink>,
+}
+
+#[derive(Serialize, Debug)]
+struct GraphNode {
+    id: String,
+    title: String,
+    video_id: Option<String>,
+    channel: Option<String>,
+    date: Option<chrono::NaiveDate>,
+    tags: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct GraphEdge {
+    /// Id of the note the link is in.
+    source: String,
+    /// Id of the note it points at.
+    target: String,
+    via: links::LinkTarget,
+}
+
+#[derive(Serialize, Debug)]
+struct GraphResponse {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize, Debug)]
+struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct TaggedNotesResponse {
+    tag: String,
+    notes: Vec<NoteSummary>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TagRenameRequest {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, Debug)]
+struct TagRenameResponse {
+    from: String,
+    to: String,
+    /// Ids of the notes that were rewritten.
+    renamed: Vec<String>,
+    /// Notes that had the tag but could not be rewritten.
+    errors: Vec<String>,
 }
 
 struct State {
     config: Config,
     notes_map: Arc<Mutex<HashMap<String, String>>>,
+    jobs: Arc<Mutex<Jobs>>,
+    settings: Settings,
+    downloader: Arc<dyn Downloader>,
+    catalog: Arc<Mutex<Catalog>>,
+    file_search: Arc<Mutex<FileSearch>>,
+    metadata: Arc<MetadataCache>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    transcript_index: Arc<Mutex<TranscriptIndex>>,
+    notes_index: Arc<Mutex<NotesIndex>>,
+    /// Notes to index again, see [`notes_index::start`].
+    notes_changed: mpsc::UnboundedSender<PathBuf>,
+    /// Held while a note is checked and written, so saves from the extension
+    /// and rewrites by the server don't interleave.
+    note_writes: Arc<Mutex<()>>,
+}
+
+impl State {
+    fn download_context(&self) -> DownloadContext {
+        DownloadContext {
+            jobs: self.jobs.clone(),
+            downloader: self.downloader.clone(),
+            catalog: self.catalog.clone(),
+            search_dirs: self.config.search_dirs.clone(),
+            notes_dir: self.config.notes_dir.clone(),
+        }
+    }
+}
+
+/// Runs a subcommand as a job like the server would, printing its log.
+#[tokio::main]
+async fn run_command(
+    config: Config,
+    command: Command,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let jobs = Arc::new(Mutex::new(Jobs::new(config.data_dir.join("logs"))?));
+    let settings = Settings::load(&config.settings)?;
+    let ctx = DownloadContext {
+        jobs: jobs.clone(),
+        downloader: downloader::create(&settings.downloader),
+        catalog: Arc::new(Mutex::new(Catalog::load(
+            config.data_dir.join("catalog.json"),
+        )?)),
+        search_dirs: config.search_dirs.clone(),
+        notes_dir: config.notes_dir.clone(),
+    };
+    let metadata = MetadataCache::new(config.data_dir.join("metadata"))?;
+
+    let (job, result) = match command {
+        Command::Chapters {
+            video_id,
+            format,
+            replace,
+        } => {
+            let request = ChapterRequest {
+                video_id,
+                format,
+                replace,
+            };
+            let duration = metadata
+                .get(&request.video_id)
+                .await
+                .and_then(|metadata| metadata.duration);
+            let job = jobs.lock().await.start("chapters", &request.video_id);
+            let result = jobs::run(jobs.clone(), job.clone(), |log| async move {
+                chapters::write(
+                    &ctx,
+                    &settings.ffmpeg,
+                    &settings.chapters,
+                    duration,
+                    &request,
+                    &log,
+                )
+                .await
+                .map(|_| ())
+            })
+            .await;
+            (job, result)
+        }
+    };
+    print!(
+        "{}",
+        std::fs::read_to_string(&job.log_path).unwrap_or_default()
+    );
+    Ok(result?)
+}
+
+/// Serves the downloaded subtitles of a video as clean segments, in JSON or
+/// with `format=text` as one `[M:SS] text` line per segment. `lang` picks
+/// the subtitle file, otherwise the newest is used.
+async fn handle_transcript(
+    req: Request<Body>,
+    state: Arc<Mutex<State>>,
+    video_id: &str,
+) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let catalog = state.lock().await.catalog.clone();
+    let files = catalog
+        .lock()
+        .await
+        .get(video_id)
+        .map(subtitles::transcript_files)
+        .unwrap_or_default();
+    let languages = files
+        .iter()
+        .filter_map(|file| subtitles::language(file))
+        .unique()
+        .collect::<Vec<String>>();
+    let Some(file) = subtitles::pick(&files, query_map.get("lang").map(String::as_str)) else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No downloaded subtitles for this video".into())
+            .unwrap();
+    };
+
+    let path = file.clone();
+    let segments = match tokio::task::spawn_blocking(move || subtitles::parse_file(&path))
+        .await
+        .unwrap()
+    {
+        Ok(it) => it,
+        Err(err) => {
+            error!("Error reading transcript: {}", err);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error reading transcript".into())
+                .unwrap();
+        }
+    };
+    if query_map
+        .get("format")
+        .is_some_and(|format| format == "text")
+    {
+        let text = segments
+            .iter()
+            .map(|segment| {
+                format!(
+                    "[{}] {}\n",
+                    timestamps::format_timestamp(segment.start),
+                    segment.text
+                )
+            })
+            .collect::<String>();
+        return Response::builder()
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(text.into())
+            .unwrap();
+    }
+    let transcript = TranscriptResponse {
+        video_id: video_id.to_string(),
+        language: subtitles::language(&file),
+        path: file,
+        languages,
+        segments,
+    };
+    Response::new(serde_json::to_string(&transcript).unwrap().into())
+}
+
+/// Lists notes a page at a time. `dir` keeps the notes in a dated folder
+/// like `2024/01`, `sort` is one of `date` (the default), `created`,
+/// `modified`, `title` or `size`, and `order` is `asc` or `desc`, which is
+/// the default for everything but titles. `offset` and `limit` (default
+/// 50) pick the page.
+async fn handle_notes_list(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let sort = query_map.get("sort").map(String::as_str).unwrap_or("date");
+    let descending = match query_map.get("order").map(String::as_str) {
+        None => sort != "title",
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(other) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Unknown order {:?}, expected asc or desc", other).into())
+                .unwrap();
+        }
+    };
+    let offset = query_map
+        .get("offset")
+        .and_then(|offset| offset.parse::<usize>().ok())
+        .unwrap_or(0);
+    let limit = query_map
+        .get("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(50);
+
+    let (notes_index, catalog) = {
+        let dastate = state.lock().await;
+        (dastate.notes_index.clone(), dastate.catalog.clone())
+    };
+    let mut notes = {
+        let notes_index = notes_index.lock().await;
+        match query_map
+            .get("dir")
+            .filter(|dir| !dir.trim_matches('/').is_empty())
+        {
+            Some(dir) => notes_index.notes_in(dir).cloned().collect::<Vec<_>>(),
+            None => notes_index.notes().cloned().collect::<Vec<_>>(),
+        }
+    };
+    match sort {
+        "date" => notes.sort_by_key(|note| (note.date, note.modified)),
+        "created" => notes.sort_by_key(|note| note.created),
+        "modified" => notes.sort_by_key(|note| note.modified),
+        "title" => {
+            notes.sort_by_cached_key(|note| notes::title_from_note_id(&note.id).to_lowercase())
+        }
+        "size" => notes.sort_by_key(|note| note.size),
+        other => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(
+                    format!(
+                        "Unknown sort {:?}, expected date, created, modified, title or size",
+                        other
+                    )
+                    .into(),
+                )
+                .unwrap();
+        }
+    }
+    if descending {
+        notes.reverse();
+    }
+
+    let total = notes.len();
+    let page = {
+        let catalog = catalog.lock().await;
+        notes
+            .into_iter()
+            .skip(offset)
+            .take(limit)
+            .map(|note| NoteSummary::new(note, &catalog))
+            .collect::<Vec<NoteSummary>>()
+    };
+    let list = NoteListResponse {
+        total,
+        offset,
+        limit,
+        notes: page,
+    };
+    Response::new(serde_json::to_string(&list).unwrap().into())
+}
+
+/// Serves the notes linking to a note, by its id, title or video id.
+async fn handle_backlinks(state: Arc<Mutex<State>>, id: &str) -> Response<Body> {
+    let id = percent_encoding::percent_decode_str(id)
+        .decode_utf8_lossy()
+        .into_owned();
+    let (notes_index, catalog) = {
+        let dastate = state.lock().await;
+        (dastate.notes_index.clone(), dastate.catalog.clone())
+    };
+    let (note, mut backlinks) = {
+        let notes_index = notes_index.lock().await;
+        let Some(note) = notes_index.resolve(&links::LinkTarget::Title(id.clone())) else {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(format!("No note {:?}", id).into())
+                .unwrap();
+        };
+        let backlinks = notes_index
+            .links()
+            .into_iter()
+            .filter(|(_, to, _)| to.path == note.path)
+            .map(|(from, _, link)| (from.clone(), link.clone()))
+            .collect::<Vec<(IndexedNote, links::NoteLink)>>();
+        (note.clone(), backlinks)
+    };
+    backlinks.sort_by_key(|(from, _)| std::cmp::Reverse((from.date, from.modified)));
+    let catalog = catalog.lock().await;
+    let response = BacklinksResponse {
+        title: notes::title_from_note_id(&note.id).to_string(),
+        id: note.id,
+        backlinks: backlinks
+            .into_iter()
+            .map(|(from, link)| Backlink {
+                note: NoteSummary::new(from, &catalog),
+                link,
+            })
+            .collect(),
+    };
+    Response::new(serde_json::to_string(&response).unwrap().into())
+}
+
+/// Serves every note and the links between them. With `linked=true`, notes
+/// without links either way are left out.
+async fn handle_graph(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let linked_only = query_map
+        .get("linked")
+        .is_some_and(|linked| linked == "true");
+    let notes_index = state.lock().await.notes_index.clone();
+    let notes_index = notes_index.lock().await;
+    let edges = notes_index
+        .links()
+        .into_iter()
+        .map(|(from, to, link)| GraphEdge {
+            source: from.id.clone(),
+            target: to.id.clone(),
+            via: link.target.clone(),
+        })
+        .collect::<Vec<GraphEdge>>();
+    let linked = edges
+        .iter()
+        .flat_map(|edge| [edge.source.as_str(), edge.target.as_str()])
+        .collect::<HashSet<&str>>();
+    let nodes = notes_index
+        .notes()
+        .filter(|note| !linked_only || linked.contains(note.id.as_str()))
+        .map(|note| GraphNode {
+            id: note.id.clone(),
+            title: notes::title_from_note_id(&note.id).to_string(),
+            video_id: note.video_id.clone(),
+            channel: note.channel.clone(),
+            date: note.date,
+            tags: note.tags.clone(),
+        })
+        .collect::<Vec<GraphNode>>();
+    let graph = GraphResponse { nodes, edges };
+    Response::new(serde_json::to_string(&graph).unwrap().into())
+}
+
+/// Serves the notes with a tag, newest first.
+async fn handle_tagged_notes(state: Arc<Mutex<State>>, tag: &str) -> Response<Body> {
+    let tag = tags::normalize(&percent_encoding::percent_decode_str(tag).decode_utf8_lossy());
+    let (notes_index, catalog) = {
+        let dastate = state.lock().await;
+        (dastate.notes_index.clone(), dastate.catalog.clone())
+    };
+    let mut notes = notes_index
+        .lock()
+        .await
+        .tagged(&tag)
+        .cloned()
+        .collect::<Vec<IndexedNote>>();
+    if notes.is_empty() {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(format!("No notes tagged {:?}", tag).into())
+            .unwrap();
+    }
+    notes.sort_by_key(|note| std::cmp::Reverse((note.date, note.modified)));
+    let catalog = catalog.lock().await;
+    let tagged = TaggedNotesResponse {
+        tag,
+        notes: notes
+            .into_iter()
+            .map(|note| NoteSummary::new(note, &catalog))
+            .collect(),
+    };
+    Response::new(serde_json::to_string(&tagged).unwrap().into())
+}
+
+/// Renames a tag in every note that has it, both hashtags and front matter.
+/// Each note is rewritten with [`notes::rewrite`] and indexed again
+/// afterwards.
+async fn handle_tag_rename(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request: TagRenameRequest = match serde_json::from_slice(&whole_body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid tag rename request: {}", err).into())
+                .unwrap();
+        }
+    };
+    let from = tags::normalize(&request.from);
+    let to = tags::normalize(&request.to);
+    if from.is_empty() || !tags::is_valid(&to) || from == to {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(format!("Can't rename tag {:?} to {:?}", from, to).into())
+            .unwrap();
+    }
+    let (notes_index, notes_changed, note_writes) = {
+        let dastate = state.lock().await;
+        (
+            dastate.notes_index.clone(),
+            dastate.notes_changed.clone(),
+            dastate.note_writes.clone(),
+        )
+    };
+    let paths = notes_index
+        .lock()
+        .await
+        .tagged(&from)
+        .map(|note| note.path.clone())
+        .collect::<Vec<PathBuf>>();
+    if paths.is_empty() {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(format!("No notes tagged {:?}", from).into())
+            .unwrap();
+    }
+
+    let (old, new) = (from.clone(), to.clone());
+    let (renamed, errors) = tokio::task::spawn_blocking(move || {
+        let mut renamed = Vec::new();
+        let mut errors = Vec::new();
+        for path in paths {
+            // Saves the extension makes on top of the old text are turned
+            // down once this is written, see `/set_note`
+            let _writing = note_writes.blocking_lock();
+            let result = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+                .and_then(|content| match tags::rename(&content, &old, &new) {
+                    Some(content) => notes::rewrite(&path, &content).map(|_| true),
+                    None => Ok(false),
+                });
+            match result {
+                Ok(true) => renamed.push(path),
+                Ok(false) => {}
+                Err(err) => errors.push(err),
+            }
+        }
+        (renamed, errors)
+    })
+    .await
+    .unwrap();
+    for err in &errors {
+        error!("Error renaming tag: {}", err);
+    }
+    info!(
+        "Renamed tag {:?} to {:?} in {} notes",
+        from,
+        to,
+        renamed.len()
+    );
+    let response = TagRenameResponse {
+        from,
+        to,
+        renamed: renamed
+            .iter()
+            .filter_map(|path| path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .collect(),
+        errors,
+    };
+    for path in renamed {
+        let _ = notes_changed.send(path);
+    }
+    Response::new(serde_json::to_string(&response).unwrap().into())
+}
+
+/// Searches the notes index. `q` takes words, `"phrases"` and `prefixes*`,
+/// all of which must match. `from` and `to` (YYYY-MM-DD), `channel` and
+/// `tag` narrow the results down.
+async fn handle_notes_search(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let query = query_map.get("q").map(|q| q.trim()).unwrap_or_default();
+    if query.is_empty() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("Missing search query q".into())
+            .unwrap();
+    }
+    let limit = query_map
+        .get("limit")
+        .and_then(|limit| limit.parse::<usize>().ok())
+        .unwrap_or(20);
+    let date = |key: &str| match query_map.get(key).filter(|date| !date.is_empty()) {
+        Some(date) => chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("Invalid {} date {:?}, expected YYYY-MM-DD", key, date)),
+        None => Ok(None),
+    };
+    let (from, to) = match (date("from"), date("to")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(err), _) | (_, Err(err)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+    let filters = notes_index::Filters {
+        from,
+        to,
+        channel: query_map
+            .get("channel")
+            .filter(|it| !it.is_empty())
+            .cloned(),
+        tag: query_map.get("tag").filter(|it| !it.is_empty()).cloned(),
+    };
+    let notes_index = state.lock().await.notes_index.clone();
+    let hits = notes_index.lock().await.search(query, &filters, limit);
+    let hits = tokio::task::spawn_blocking(move || {
+        hits.into_iter()
+            .map(|mut hit| {
+                if let Ok(content) = std::fs::read_to_string(&hit.note.path) {
+                    hit.snippets = notes_index::snippets(&content, &hit.positions);
+                }
+                hit
+            })
+            .collect::<Vec<notes_index::NoteHit>>()
+    })
+    .await
+    .unwrap();
+    Response::new(serde_json::to_string(&hits).unwrap().into())
+}
+
+/// Serves the transcript of a video interleaved with its note and events,
+/// as Markdown or with `format=html` as a page. `lang` picks the subtitles
+/// like for `/transcript`.
+async fn handle_annotated(
+    req: Request<Body>,
+    state: Arc<Mutex<State>>,
+    video_id: &str,
+) -> Response<Body> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let format = match annotated::Format::from_query(query_map.get("format").map(String::as_str)) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+    let (catalog, metadata, notes_dir) = {
+        let dastate = state.lock().await;
+        (
+            dastate.catalog.clone(),
+            dastate.metadata.clone(),
+            dastate.config.notes_dir.clone(),
+        )
+    };
+    let (files, title) = {
+        let catalog = catalog.lock().await;
+        let entry = catalog.get(video_id);
+        (
+            entry.map(subtitles::transcript_files).unwrap_or_default(),
+            entry.and_then(|entry| entry.title.clone()),
+        )
+    };
+    let title = match title {
+        Some(title) => title,
+        None => metadata
+            .get(video_id)
+            .await
+            .map(|metadata| metadata.title)
+            .unwrap_or_else(|| video_id.to_string()),
+    };
+    let transcript = subtitles::pick(&files, query_map.get("lang").map(String::as_str));
+
+    let id = video_id.to_string();
+    let loaded = tokio::task::spawn_blocking(move || {
+        let segments = match transcript {
+            Some(path) => subtitles::parse_file(&path)?,
+            None => Vec::new(),
+        };
+        let note = match notes::find_note(&notes_dir, &id) {
+            Some(note) => Some(
+                std::fs::read_to_string(&note)
+                    .map_err(|e| format!("Failed to read {}: {}", note.display(), e))?,
+            ),
+            None => None,
+        };
+        Ok::<_, String>((segments, note))
+    })
+    .await
+    .unwrap();
+    match loaded {
+        Ok((segments, note)) if segments.is_empty() && note.is_none() => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No downloaded subtitles or note for this video".into())
+            .unwrap(),
+        Ok((segments, note)) => Response::builder()
+            .header("Content-Type", format.content_type())
+            .body(annotated::render(format, video_id, &title, &segments, note.as_deref()).into())
+            .unwrap(),
+        Err(err) => {
+            error!("Error reading annotated transcript: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error reading transcript or note".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Writes the timed lines of a video's note as a subtitle file next to its
+/// downloaded media, for rewatching it with the notes popping up.
+async fn handle_note_subtitles(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request: OverlayRequest = match serde_json::from_slice(&whole_body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid subtitles request: {}", err).into())
+                .unwrap();
+        }
+    };
+    let (catalog, metadata, notes_dir) = {
+        let dastate = state.lock().await;
+        (
+            dastate.catalog.clone(),
+            dastate.metadata.clone(),
+            dastate.config.notes_dir.clone(),
+        )
+    };
+    let (media, title) = {
+        let catalog = catalog.lock().await;
+        let entry = catalog.get(&request.video_id);
+        (
+            entry
+                .and_then(|entry| entry.media_path())
+                .map(PathBuf::from),
+            entry.and_then(|entry| entry.title.clone()),
+        )
+    };
+    let Some(media) = media else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No local copy of this video".into())
+            .unwrap();
+    };
+    let cached = metadata.get(&request.video_id).await;
+    let duration = cached.as_ref().and_then(|metadata| metadata.duration);
+    let title = title
+        .or(cached.map(|metadata| metadata.title))
+        .unwrap_or_else(|| request.video_id.clone());
+
+    let written = tokio::task::spawn_blocking(move || {
+        let Some(note) = notes::find_note(&notes_dir, &request.video_id) else {
+            return Ok(None);
+        };
+        overlay::write(&note, &media, &title, &request, duration)
+    })
+    .await
+    .unwrap();
+    match written {
+        Ok(Some((path, cues))) => Response::new(
+            serde_json::to_string(&OverlayResponse { path, cues })
+                .unwrap()
+                .into(),
+        ),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No timestamped lines in a note for this video".into())
+            .unwrap(),
+        Err(err) => {
+            error!("Error writing note subtitles: {}", err);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error writing note subtitles".into())
+                .unwrap()
+        }
+    }
+}
+
+/// Starts a job writing the timestamps in a video's note into its
+/// downloaded file as chapters.
+async fn handle_chapters(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request: ChapterRequest = match serde_json::from_slice(&whole_body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid chapters request: {}", err).into())
+                .unwrap();
+        }
+    };
+    let (ctx, metadata, ffmpeg, chapter_settings) = {
+        let dastate = state.lock().await;
+        (
+            dastate.download_context(),
+            dastate.metadata.clone(),
+            dastate.settings.ffmpeg.clone(),
+            dastate.settings.chapters.clone(),
+        )
+    };
+    let has_media = ctx
+        .catalog
+        .lock()
+        .await
+        .get(&request.video_id)
+        .is_some_and(|entry| entry.media_path().is_some());
+    if !has_media {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No local copy of this video".into())
+            .unwrap();
+    }
+    let duration = metadata
+        .get(&request.video_id)
+        .await
+        .and_then(|metadata| metadata.duration);
+
+    let job = jobs::spawn(
+        ctx.jobs.clone(),
+        "chapters",
+        &request.video_id.clone(),
+        move |log| async move {
+            chapters::write(&ctx, &ffmpeg, &chapter_settings, duration, &request, &log)
+                .await
+                .map(|_| ())
+        },
+    )
+    .await;
+    Response::new(
+        serde_json::to_string(&JobStarted::new(&job, None))
+            .unwrap()
+            .into(),
+    )
 }
 
 #[tokio::main]
@@ -178,10 +1149,57 @@ async fn run_server<'a>(config: Config) -> Result<(), Box<dyn std::error::Error
         .with_all_versions_alpn()
         .with_incoming(incoming);
 
+    let jobs = Jobs::new(config.data_dir.join("logs"))?;
+    let settings = Settings::load(&config.settings)?;
+    let downloader = downloader::create(&settings.downloader);
+    let catalog = Arc::new(Mutex::new(Catalog::load(
+        config.data_dir.join("catalog.json"),
+    )?));
+    let file_search = Arc::new(Mutex::new(FileSearch::new(&settings.search)?));
+    let metadata = Arc::new(MetadataCache::new(config.data_dir.join("metadata"))?);
+    let subscriptions = Arc::new(Mutex::new(Subscriptions::load(
+        config.data_dir.join("subscriptions.json"),
+    )?));
+    let transcript_index = Arc::new(Mutex::new(TranscriptIndex::default()));
+    {
+        let catalog = catalog.clone();
+        let file_search = file_search.clone();
+        let search_dirs = config.search_dirs.clone();
+        let transcript_index = transcript_index.clone();
+        tokio::spawn(async move {
+            if let Err(err) = catalog::rescan(&catalog, file_search, search_dirs).await {
+                error!("Error scanning search dirs: {}", err);
+            }
+            // So the first search doesn't wait for every transcript to be parsed
+            transcript_index::refresh(&transcript_index, &catalog).await;
+        });
+    }
+    let notes_index = Arc::new(Mutex::new(NotesIndex::load(
+        config.data_dir.join("notes_index.json"),
+        config.notes_dir.clone(),
+    )));
+    let notes_changed = notes_index::start(notes_index.clone(), metadata.clone());
     let initial_state = State {
         config: config.clone(),
         notes_map: Arc::new(Mutex::new(HashMap::new())),
+        jobs: Arc::new(Mutex::new(jobs)),
+        settings,
+        downloader,
+        catalog,
+        file_search,
+        metadata,
+        subscriptions: subscriptions.clone(),
+        transcript_index,
+        notes_index,
+        notes_changed,
+        note_writes: Arc::new(Mutex::new(())),
     };
+    tokio::spawn(subscriptions::check_periodically(
+        subscriptions,
+        initial_state.download_context(),
+        initial_state.settings.clone(),
+        config.downloads_dir.clone(),
+    ));
     let state = Arc::new(Mutex::new(initial_state));
     let service = make_service_fn(move |_| {
         let state = state.clone();
@@ -211,11 +1229,7 @@ fn get_path_for_note_id(
         return Ok(PathBuf::from(path));
     }
 
-    let invalid_chars: Vec<char> = vec!['<', '>', ':', '"', '/', '\\', '|', '?', '*', '\n'];
-    let sanitized_id = id
-        .chars()
-        .map(|c| if invalid_chars.contains(&c) { '_' } else { c })
-        .collect::<String>();
+    let sanitized_id = sanitize_filename(id);
 
     let dated_dir = get_dated_dir(notes_dir)?;
     let file_path = dated_dir.join(format!("{}.txt", &sanitized_id));
@@ -225,31 +1239,12 @@ fn get_path_for_note_id(
     Ok(file_path)
 }
 
-async fn search(text: &str, dir: &PathBuf) -> Result<Vec<String>, String> {
-    let shell_code = format!(
-        "Get-ChildItem -Recurse {} | ForEach-Object {{ $_.FullName}} | rg --ignore-case --fixed-strings --regexp '{}'",
-        dir.to_str().unwrap(),
-        text
-    );
-    debug!("shell_code: {}", shell_code);
-    let output = tokio::process::Command::new("pwsh")
-        .arg("-c")
-        .arg(shell_code)
-        .output()
-        .await
-        .expect("Failed to execute command");
-
-    if output.status.success() {
-        let stdout_str = String::from_utf8_lossy(&output.stdout);
-        let results: Vec<String> = stdout_str
-            .lines()
-            .map(|s| s.to_string())
-            .filter(|s| !s.is_empty())
-            .collect();
-        Ok(results)
-    } else {
-        Err(String::from_utf8_lossy(&output.stderr).to_string())
-    }
+/// Replaces characters that aren't allowed in file names on Windows.
+fn sanitize_filename(name: &str) -> String {
+    let invalid_chars: Vec<char> = vec!['<', '>', ':', '"', '/', '\\', '|', '?', '*', '\n'];
+    name.chars()
+        .map(|c| if invalid_chars.contains(&c) { '_' } else { c })
+        .collect::<String>()
 }
 
 fn get_dated_dir(parent_dir: &PathBuf) -> std::io::Result<PathBuf> {
@@ -264,54 +1259,504 @@ fn get_dated_dir(parent_dir: &PathBuf) -> std::io::Result<PathBuf> {
     Ok(dated_dir)
 }
 
-async fn get_ytdlp_filename(url: &str) -> Result<String, String> {
-    let output = tokio::process::Command::new("yt-dlp")
-        .arg("--encoding")
-        .arg("utf-8")
-        .arg("--print")
-        .arg("filename")
-        // .arg("--cookies-from-browser")
-        // .arg("edge")
-        .arg("--windows-filenames")
-        .arg("--embed-metadata")
-        .arg(url)
-        .output()
+/// What a download request asks for, shared by the single video and
+/// playlist endpoints.
+struct DownloadSetup {
+    /// The request body, which holds the URL.
+    body: String,
+    profile_name: String,
+    profile: settings::Profile,
+    downloads: DownloadContext,
+    /// Where the files go, the downloads dir's folder for today.
+    dated_dir: PathBuf,
+    console_command: Vec<String>,
+    show_console: bool,
+}
+
+/// Reads the body and resolves the `profile` and `show_console` query
+/// params, or returns the response rejecting the request.
+async fn download_setup(
+    req: Request<Body>,
+    state: &Arc<Mutex<State>>,
+    default_profile: &str,
+) -> Result<DownloadSetup, Response<Body>> {
+    let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+        .into_owned()
+        .collect::<HashMap<String, String>>();
+    let profile_name = query_map
+        .get("profile")
+        .map(String::as_str)
+        .unwrap_or(default_profile)
+        .to_string();
+
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let body = String::from_utf8_lossy(&whole_body).into_owned();
+
+    let (dir, downloads, profile, console_command) = {
+        let dastate = state.lock().await;
+        let profile = match dastate.settings.profile(&profile_name) {
+            Ok(it) => it.clone(),
+            Err(err) => {
+                error!("{}", err);
+                return Err(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(err.into())
+                    .unwrap());
+            }
+        };
+        (
+            dastate.config.downloads_dir.clone(),
+            dastate.download_context(),
+            profile,
+            dastate.settings.console_command.clone(),
+        )
+    };
+    let show_console = match query_map.get("show_console").map(|v| v.parse::<bool>()) {
+        None => profile.show_console,
+        Some(Ok(show_console)) => show_console,
+        Some(Err(_)) => {
+            return Err(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Invalid show_console parameter".into())
+                .unwrap());
+        }
+    };
+    let dated_dir = match get_dated_dir(&dir) {
+        Ok(it) => it,
+        Err(err) => {
+            error!("Error getting dated dir: {}", err);
+            return Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error getting dated dir".into())
+                .unwrap());
+        }
+    };
+    Ok(DownloadSetup {
+        body,
+        profile_name,
+        profile,
+        downloads,
+        dated_dir,
+        console_command,
+        show_console,
+    })
+}
+
+/// Starts a download job for the URL in the request body using the profile
+/// named by the `profile` query param, falling back to `default_profile`.
+/// `show_console=true` additionally opens a window following the job log.
+async fn handle_download(
+    req: Request<Body>,
+    state: Arc<Mutex<State>>,
+    default_profile: &str,
+) -> Response<Body> {
+    let DownloadSetup {
+        body,
+        profile_name,
+        profile,
+        downloads,
+        dated_dir,
+        console_command,
+        show_console,
+    } = match download_setup(req, &state, default_profile).await {
+        Ok(it) => it,
+        Err(response) => return response,
+    };
+    let url = match youtube::parse_url(&body) {
+        Ok(url @ YoutubeUrl::Video { .. }) => url,
+        Ok(YoutubeUrl::Playlist { .. }) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Playlist URLs are downloaded with /download_playlist".into())
+                .unwrap();
+        }
+        Ok(YoutubeUrl::Channel { .. }) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Channel URLs can't be downloaded here, subscribe to them with /subscriptions instead".into())
+                .unwrap();
+        }
+        Err(err) => {
+            error!("Rejecting download of {:?}: {}", body, err);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+
+    let filename = match downloads.downloader.probe_filename(&url, &profile).await {
+        Ok(it) => it,
+        Err(err) => {
+            error!("Error getting filename: {}", err);
+            return Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(format!("Error getting filename: {}", err).into())
+                .unwrap();
+        }
+    };
+
+    let canonical_url = url.canonical();
+    let request = DownloadRequest {
+        url,
+        profile,
+        dir: dated_dir,
+    };
+    let job = jobs::spawn(
+        downloads.jobs.clone(),
+        &format!("download:{}", profile_name),
+        &canonical_url,
+        move |log| async move { downloads.download_video(&request, &log).await },
+    )
+    .await;
+    if show_console {
+        if let Err(err) = jobs::open_console(&console_command, &job.log_path) {
+            error!("{}", err);
+        }
+    }
+
+    let started = JobStarted::new(&job, Some(filename));
+    Response::new(serde_json::to_string(&started).unwrap().into())
+}
+
+/// Expands a playlist and downloads its entries one at a time, each in a
+/// child job of the returned playlist job. Entries the catalog already has
+/// files of the profile's kind for are skipped.
+async fn handle_download_playlist(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let DownloadSetup {
+        body,
+        profile_name,
+        profile,
+        downloads,
+        dated_dir,
+        console_command,
+        show_console,
+    } = match download_setup(req, &state, "video").await {
+        Ok(it) => it,
+        Err(response) => return response,
+    };
+    let url = match youtube::parse_playlist_url(&body) {
+        Ok(it) => it,
+        Err(err) => {
+            error!("Rejecting playlist download of {:?}: {}", body, err);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+
+    let jobs = downloads.jobs.clone();
+    let job = jobs
+        .lock()
         .await
-        .expect("Failed to execute command");
-
-    if output.status.success() {
-        let fname = String::from_utf8_lossy(&output.stdout);
-        let fname_trimmed = fname.trim_end_matches('\n');
-        Ok(fname_trimmed.to_string())
-    } else {
-        Err(String::from_utf8_lossy(&output.stderr).to_string())
+        .start(&format!("playlist:{}", profile_name), &url.canonical());
+    let parent_id = job.id;
+    tokio::spawn(jobs::run(jobs, job.clone(), move |log| async move {
+        let playlist = downloads.downloader.playlist(&url).await?;
+        log.line(&format!(
+            "{} has {} entries",
+            playlist.title.as_deref().unwrap_or(&playlist.id),
+            playlist.entries.len()
+        ))
+        .await?;
+
+        let ids = playlist
+            .entries
+            .iter()
+            .map(|entry| entry.id.clone())
+            .collect::<Vec<String>>();
+        let downloaded = downloads.downloaded(&ids, &profile).await;
+        let mut pending = Vec::new();
+        for (id, downloaded) in ids.into_iter().zip(downloaded) {
+            if !youtube::is_video_id(&id) {
+                log.line(&format!("Skipping {}: not a video", id)).await?;
+            } else if downloaded {
+                log.line(&format!("Skipping {}: already downloaded", id))
+                    .await?;
+            } else {
+                pending.push(id);
+            }
+        }
+        let total = playlist.entries.len();
+        downloads
+            .jobs
+            .lock()
+            .await
+            .set_progress(parent_id, total, total - pending.len());
+
+        let failed = downloads
+            .download_children(
+                parent_id,
+                &log,
+                &profile_name,
+                &profile,
+                &dated_dir,
+                &pending,
+            )
+            .await?;
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} of {} downloads failed",
+                failed.len(),
+                pending.len()
+            ))
+        }
+    }));
+    if show_console {
+        if let Err(err) = jobs::open_console(&console_command, &job.log_path) {
+            error!("{}", err);
+        }
     }
+
+    let started = JobStarted::new(&job, None);
+    Response::new(serde_json::to_string(&started).unwrap().into())
 }
-async fn get_ytdlp_audio_filename(url: &str) -> Result<String, String> {
-    let output = tokio::process::Command::new("yt-dlp")
-        .arg("--encoding")
-        .arg("utf-8")
-        .arg("--print")
-        .arg("filename")
-        // .arg("--cookies-from-browser")
-        // .arg("edge")
-        .arg("-f")
-        .arg("bestaudio")
-        .arg("--extract-audio")
-        .arg("--windows-filenames")
-        .arg("--embed-metadata")
-        .arg(url)
-        .output()
+
+/// Starts a job cutting a segment out of a downloaded video into the clips
+/// folder of the downloads dir. The clip is recorded in the note's metadata,
+/// and the finished job has a line linking it for the extension to add.
+async fn handle_clip(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request: ClipRequest = match serde_json::from_slice(&whole_body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid clip request: {}", err).into())
+                .unwrap();
+        }
+    };
+    let range = request.start.seconds().and_then(|start| {
+        let end = request.end.seconds()?;
+        if start < end {
+            Ok((start, end))
+        } else {
+            Err(format!(
+                "Clip start {} is not before its end {}",
+                start, end
+            ))
+        }
+    });
+    let (start, end) = match range {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+    let video_id = request.video_id;
+
+    let (catalog, jobs, ffmpeg, downloads_dir, notes_dir) = {
+        let dastate = state.lock().await;
+        (
+            dastate.catalog.clone(),
+            dastate.jobs.clone(),
+            dastate.settings.ffmpeg.clone(),
+            dastate.config.downloads_dir.clone(),
+            dastate.config.notes_dir.clone(),
+        )
+    };
+    let (source, title) = {
+        let catalog = catalog.lock().await;
+        let entry = catalog.get(&video_id);
+        (
+            entry
+                .and_then(|entry| entry.media_path())
+                .map(PathBuf::from),
+            entry.and_then(|entry| entry.title.clone()),
+        )
+    };
+    let Some(source) = source else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No local copy of this video".into())
+            .unwrap();
+    };
+    let clips_dir = downloads_dir.join("clips");
+    if let Err(err) = create_dir_all(&clips_dir) {
+        error!("Error creating clips dir: {}", err);
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Error creating clips dir".into())
+            .unwrap();
+    }
+    // No [id] in the name, so the catalog doesn't take clips for the video itself
+    let output_stem = clips_dir.join(sanitize_filename(&format!(
+        "{} ({}) {}-{}",
+        title.as_deref().unwrap_or("clip"),
+        video_id,
+        timestamps::precise_file_label(start),
+        timestamps::precise_file_label(end)
+    )));
+
+    let target = format!(
+        "{} {}-{}",
+        video_id,
+        timestamps::format_timestamp(start),
+        timestamps::format_timestamp(end)
+    );
+    let job = jobs.lock().await.start("clip", &target);
+    let job_id = job.id;
+    let job_list = jobs.clone();
+    tokio::spawn(jobs::run(jobs, job.clone(), move |log| async move {
+        let clip = ffmpeg::clip(
+            &ffmpeg,
+            &log,
+            &source,
+            start,
+            end,
+            &output_stem,
+            request.reencode,
+        )
+        .await?;
+        log.line(&format!("Wrote {}", clip.display())).await?;
+        job_list.lock().await.add_note_line(
+            job_id,
+            jobs::NoteLine {
+                line: format!(
+                    "[✂ {}–{}](<{}>)",
+                    timestamps::format_timestamp(start),
+                    timestamps::format_timestamp(end),
+                    clip.display()
+                ),
+                after: None,
+            },
+        );
+        let linked = tokio::task::spawn_blocking(move || {
+            notes::update_meta(&notes_dir, &video_id, |meta| {
+                meta.clips.push(notes::NoteClip {
+                    path: clip,
+                    start,
+                    end,
+                    recorded: Local::now(),
+                })
+            })
+        })
         .await
-        .expect("Failed to execute command");
-
-    if output.status.success() {
-        let fname = String::from_utf8_lossy(&output.stdout);
-        let fname_trimmed = fname.trim_end_matches('\n');
-        Ok(fname_trimmed.to_string())
-    } else {
-        Err(String::from_utf8_lossy(&output.stderr).to_string())
+        .map_err(|e| format!("Note lookup panicked: {}", e))??;
+        match linked {
+            Some(note) => {
+                log.line(&format!("Recorded in note {}", note.display()))
+                    .await
+            }
+            None => log.line("No note to record the clip in").await,
+        }
+    }));
+    Response::new(
+        serde_json::to_string(&JobStarted::new(&job, None))
+            .unwrap()
+            .into(),
+    )
+}
+
+/// Body of `POST /snapshot`.
+#[derive(Deserialize, Debug)]
+struct SnapshotRequest {
+    video_id: String,
+    /// Every timestamp in the note that has no image yet when left out.
+    position: Option<Position>,
+}
+
+/// Starts a job grabbing frames of a downloaded video into its note's
+/// attachments folder. The finished job has the image lines for the
+/// extension to add to the note.
+async fn handle_snapshot(req: Request<Body>, state: Arc<Mutex<State>>) -> Response<Body> {
+    let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    let request: SnapshotRequest = match serde_json::from_slice(&whole_body) {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(format!("Invalid snapshot request: {}", err).into())
+                .unwrap();
+        }
+    };
+    let position = match request.position.as_ref().map(Position::seconds).transpose() {
+        Ok(it) => it,
+        Err(err) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(err.into())
+                .unwrap();
+        }
+    };
+    let video_id = request.video_id;
+
+    let (catalog, jobs, ffmpeg, notes_dir) = {
+        let dastate = state.lock().await;
+        (
+            dastate.catalog.clone(),
+            dastate.jobs.clone(),
+            dastate.settings.ffmpeg.clone(),
+            dastate.config.notes_dir.clone(),
+        )
+    };
+    let source = catalog
+        .lock()
+        .await
+        .get(&video_id)
+        .and_then(|entry| entry.media_path())
+        .map(PathBuf::from)
+        .filter(|path| FileKind::from_path(path) == Some(FileKind::Video));
+    let Some(source) = source else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No local video file for this video".into())
+            .unwrap();
+    };
+    let id = video_id.clone();
+    let note = tokio::task::spawn_blocking(move || notes::find_note(&notes_dir, &id))
+        .await
+        .unwrap();
+    let Some(note) = note else {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("No note for this video".into())
+            .unwrap();
+    };
+    let positions = match position {
+        Some(position) => vec![position],
+        None => {
+            let content = match tokio::fs::read_to_string(&note).await {
+                Ok(it) => it,
+                Err(err) => {
+                    error!("Error reading note {}: {}", note.display(), err);
+                    return Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body("Error reading note".into())
+                        .unwrap();
+                }
+            };
+            snapshots::missing_positions(&note, &content)
+        }
+    };
+    if positions.is_empty() {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body("No timestamps without a snapshot in the note".into())
+            .unwrap();
     }
+
+    let target = match position {
+        Some(position) => format!("{} {}", video_id, timestamps::format_timestamp(position)),
+        None => format!("{} ({} timestamps)", video_id, positions.len()),
+    };
+    let job = jobs.lock().await.start("snapshot", &target);
+    let job_id = job.id;
+    let job_list = jobs.clone();
+    tokio::spawn(jobs::run(jobs, job.clone(), move |log| async move {
+        snapshots::take(&ffmpeg, &log, &job_list, job_id, &source, &note, &positions).await
+    }));
+    Response::new(
+        serde_json::to_string(&JobStarted::new(&job, None))
+            .unwrap()
+            .into(),
+    )
 }
 
 async fn handle(
@@ -356,6 +1801,30 @@ async fn handle(
                 };
             drop(map);
 
+            let _writing = dastate.note_writes.lock().await;
+            if let Some(version) = &note.version {
+                if let Ok(current) = std::fs::read_to_string(&file_path) {
+                    let current_version = notes::version(&current);
+                    if *version != current_version {
+                        info!(
+                            "Turning down a save of \"{}\" made on top of an older version",
+                            file_path.display()
+                        );
+                        let current = Note {
+                            id: note.id,
+                            content: current,
+                            metadata_url: None,
+                            media_path: None,
+                            clips: Vec::new(),
+                            version: Some(current_version),
+                        };
+                        return Ok(Response::builder()
+                            .status(StatusCode::CONFLICT)
+                            .body(serde_json::to_string(&current).unwrap().into())
+                            .unwrap());
+                    }
+                }
+            }
             let mut file = OpenOptions::new()
                 .create(true)
                 .write(true)
@@ -369,8 +1838,12 @@ async fn handle(
                 file_path.display()
             );
             file.write_all(bytes).unwrap();
+            let _ = dastate.notes_changed.send(file_path);
 
-            let res: Response<Body> = Response::new("Note set".into());
+            let saved = NoteSaved {
+                version: notes::version(&note.content),
+            };
+            let res: Response<Body> = Response::new(serde_json::to_string(&saved).unwrap().into());
             Ok(res)
         }
         (&Method::GET, "/exists") => {
@@ -382,31 +1855,73 @@ async fn handle(
                 let decoded_search =
                     percent_encoding::percent_decode_str(search_param).decode_utf8_lossy();
 
-                let dirs = &state.lock().await.config.search_dirs;
-                let mut total_results = Vec::new();
-
-                for dir in dirs {
-                    match search(&decoded_search, dir).await {
-                        Ok(results) => {
-                            total_results.extend(results);
-                        }
-                        Err(err) => {
-                            error!("Error in search: {}", err);
-                            info!("Sending 'not found' result due to error");
-                        }
+                // Video ids are answered from the catalog when it knows them. Anything
+                // else, including ids it has not seen yet and words that merely look
+                // like ids, is searched for on disk
+                let mut exists = ExistsResponse {
+                    search: decoded_search.to_string(),
+                    matches: Vec::new(),
+                    errors: Vec::new(),
+                };
+                if youtube::is_video_id(&decoded_search) {
+                    let catalog = state.lock().await.catalog.clone();
+                    let catalog = catalog.lock().await;
+                    if let Some(entry) = catalog.get(&decoded_search) {
+                        exists.matches = entry
+                            .files
+                            .iter()
+                            .filter(|file| file.path.exists())
+                            .map(|file| FileMatch::from_catalog(&entry.video_id, file))
+                            .collect();
                     }
                 }
+                if exists.matches.is_empty() {
+                    let (dirs, file_search) = {
+                        let dastate = state.lock().await;
+                        (
+                            dastate.config.search_dirs.clone(),
+                            dastate.file_search.clone(),
+                        )
+                    };
+                    let text = decoded_search.to_string();
+                    (exists.matches, exists.errors) = tokio::task::spawn_blocking(move || {
+                        let mut file_search = file_search.blocking_lock();
+                        let mut matches = Vec::new();
+                        let mut errors = Vec::new();
+                        for dir in &dirs {
+                            match file_search.search(&text, dir) {
+                                Ok(results) => {
+                                    matches.extend(
+                                        results
+                                            .iter()
+                                            .filter_map(|path| FileMatch::from_path(path, &dirs)),
+                                    );
+                                }
+                                Err(err) => {
+                                    error!("Error in search: {}", err);
+                                    errors.push(err);
+                                }
+                            }
+                        }
+                        (matches, errors)
+                    })
+                    .await
+                    .unwrap();
+                }
 
-                let res = if total_results.is_empty() {
-                    Response::builder()
-                        .status(StatusCode::NOT_FOUND)
-                        .body("No results found".into())
-                        .unwrap()
+                // 404 strictly means "nothing there", a failed search is a server error
+                let status = if !exists.matches.is_empty() {
+                    StatusCode::OK
+                } else if !exists.errors.is_empty() {
+                    StatusCode::INTERNAL_SERVER_ERROR
                 } else {
-                    Response::new(format!("Results: {:?}", total_results).into())
+                    StatusCode::NOT_FOUND
                 };
-
-                Ok(res)
+                Ok(Response::builder()
+                    .status(status)
+                    .header("Content-Type", "application/json")
+                    .body(serde_json::to_string(&exists).unwrap().into())
+                    .unwrap())
             } else {
                 Ok(Response::builder()
                     .status(StatusCode::BAD_REQUEST)
@@ -415,102 +1930,21 @@ async fn handle(
             }
         }
 
-        (&Method::POST, "/download") => {
-            let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
-            let url = String::from_utf8(whole_body.to_vec()).unwrap();
-
-            let dir = &state.lock().await.config.downloads_dir;
-            let dated_dir = match get_dated_dir(dir) {
-                Ok(it) => it,
-                Err(err) => {
-                    error!("Error getting dated dir: {}", err);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body("Error getting dated dir".into())
-                        .unwrap());
-                }
-            };
-
-            let filename = get_ytdlp_filename(&url)
-                .await
-                .expect("Failed to get filename");
-
-            // Run the full command
-            let output = std::process::Command::new("pwsh")
-                .current_dir(&dated_dir)
-                .arg("-NoProfile")
-                .arg("-WorkingDirectory")
-                .arg("$(Get-Location)")
-                .arg("-c")
-                // .arg(format!("wt pwsh.exe -NoProfile -WorkingDirectory $(Get-Location) -c 'yt-dlp --cookies-from-browser edge --windows-filenames --embed-metadata \"{}\" && Write-Host \"\"press any key to close\"\" && $Host.UI.RawUI.ReadKey(\"\"NoEcho,IncludeKeyDown\"\")'", url))
-                .arg(format!("wt pwsh.exe -NoProfile -WorkingDirectory $(Get-Location) -c 'yt-dlp --windows-filenames --write-subs --write-auto-subs --embed-metadata \"{}\" && Write-Host \"\"press any key to close\"\" && $Host.UI.RawUI.ReadKey(\"\"NoEcho,IncludeKeyDown\"\")'", url))
-                .output()
-                .expect("Failed to execute command");
-
-            let res = if output.status.success() {
-                info!("Subprocess invoked successfully");
-                Response::new(filename.into())
-            } else {
-                error!(
-                    "Subprocess failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body("download failed".into())
-                    .unwrap()
-            };
-
-            Ok(res)
+        (&Method::POST, "/download") => Ok(handle_download(req, state, "video").await),
+        (&Method::POST, "/download_audio") => Ok(handle_download(req, state, "audio").await),
+        (&Method::POST, "/clip") => Ok(handle_clip(req, state).await),
+        (&Method::POST, "/snapshot") => Ok(handle_snapshot(req, state).await),
+        (&Method::POST, "/chapters") => Ok(handle_chapters(req, state).await),
+        (&Method::POST, "/note_subtitles") => Ok(handle_note_subtitles(req, state).await),
+        (&Method::POST, "/download_playlist") => Ok(handle_download_playlist(req, state).await),
+        (&Method::POST, "/download_subtitles") => {
+            Ok(handle_download(req, state, "subtitles").await)
         }
-
-        (&Method::POST, "/download_audio") => {
-            let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
-            let url = String::from_utf8(whole_body.to_vec()).unwrap();
-
-            let dir = &state.lock().await.config.downloads_dir;
-            let dated_dir = match get_dated_dir(dir) {
-                Ok(it) => it,
-                Err(err) => {
-                    error!("Error getting dated dir: {}", err);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body("Error getting dated dir".into())
-                        .unwrap());
-                }
-            };
-
-            let filename = get_ytdlp_audio_filename(&url)
-                .await
-                .expect("Failed to get filename");
-
-            // Run the full command
-            let output = std::process::Command::new("pwsh")
-                .current_dir(&dated_dir)
-                .arg("-NoProfile")
-                .arg("-WorkingDirectory")
-                .arg("$(Get-Location)")
-                .arg("-c")
-                // .arg(format!("wt pwsh.exe -NoProfile -WorkingDirectory $(Get-Location) -c 'yt-dlp --cookies-from-browser edge --windows-filenames --embed-metadata \"{}\" && Write-Host \"\"press any key to close\"\" && $Host.UI.RawUI.ReadKey(\"\"NoEcho,IncludeKeyDown\"\")'", url))
-                .arg(format!("wt pwsh.exe -NoProfile -WorkingDirectory $(Get-Location) -c 'yt-dlp \"{}\" -f bestaudio --extract-audio --windows-filenames --embed-metadata  && Write-Host \"\"press any key to close\"\" && $Host.UI.RawUI.ReadKey(\"\"NoEcho,IncludeKeyDown\"\")'", url))
-                .output()
-                .expect("Failed to execute command");
-
-            let res = if output.status.success() {
-                info!("Subprocess invoked successfully");
-                Response::new(filename.into())
-            } else {
-                error!(
-                    "Subprocess failed: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-                Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body("download failed".into())
-                    .unwrap()
-            };
-
-            Ok(res)
+        (&Method::GET, "/profiles") => {
+            let profiles = state.lock().await.settings.profiles.clone();
+            Ok(Response::new(
+                serde_json::to_string(&profiles).unwrap().into(),
+            ))
         }
 
         (&Method::POST, "/open_videos_folder") => {
@@ -633,6 +2067,11 @@ async fn handle(
                     }
                 };
             drop(map);
+            let templates = dastate.settings.note_templates.clone();
+            let metadata = dastate.metadata.clone();
+            let downloader = dastate.downloader.clone();
+            let catalog = dastate.catalog.clone();
+            drop(dastate);
 
             let mut content = String::new();
 
@@ -640,69 +2079,299 @@ async fn handle(
                 let mut file = OpenOptions::new().read(true).open(&file_path).unwrap();
                 file.read_to_string(&mut content).unwrap();
             } else {
-                info!("File not found: {:?} - creating empty note", file_path);
-                let file = OpenOptions::new()
+                info!("File not found: {:?} - creating new note", file_path);
+                if let Some(video_id) = notes::video_id_from_note_id(&decoded_id) {
+                    if !templates.is_empty() {
+                        match metadata
+                            .get_or_fetch(downloader.as_ref(), video_id, false)
+                            .await
+                        {
+                            Ok(metadata) => {
+                                let source = notes::source_from_note_id(&decoded_id);
+                                if let Some(template) = templates.select(source, &metadata) {
+                                    content = note_template::render(template, &metadata);
+                                }
+                            }
+                            Err(err) => {
+                                warn!("No metadata for note template: {}", err);
+                            }
+                        }
+                    }
+                }
+                let mut file = OpenOptions::new()
                     .create(true)
                     .append(true)
                     .open(&file_path)
                     .unwrap();
-                drop(file);
+                file.write_all(content.as_bytes()).unwrap();
+            }
+
+            let video_id = notes::video_id_from_note_id(&decoded_id);
+            let note_meta = notes::NoteMeta::load(&file_path);
+            let mut media_path = note_meta.media_path().map(PathBuf::from);
+            if media_path.is_none() {
+                if let Some(video_id) = video_id {
+                    media_path = catalog
+                        .lock()
+                        .await
+                        .get(video_id)
+                        .and_then(|entry| entry.media_path())
+                        .map(PathBuf::from);
+                }
             }
-            // If file not found, content remains an empty string
 
+            let version = notes::version(&content);
             let note = Note {
                 id: decoded_id.to_string(),
                 content,
+                metadata_url: video_id.map(|video_id| format!("/metadata/{}", video_id)),
+                media_path,
+                clips: note_meta.clips.into_iter().map(|clip| clip.path).collect(),
+                version: Some(version),
             };
 
             let res: Response<Body> = Response::new(serde_json::to_string(&note).unwrap().into());
             Ok(res)
         }
-        (&Method::POST, "/download_subtitles") => {
-            // Read the URL from the request body
-            let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
-            let url = String::from_utf8(whole_body.to_vec()).unwrap();
-
-            // Get the download directory (using your dated_dir helper)
-            let dir = &state.lock().await.config.downloads_dir;
-            let dated_dir = match get_dated_dir(dir) {
-                Ok(it) => it,
-                Err(err) => {
-                    error!("Error getting dated dir: {}", err);
-                    return Ok(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body("Error getting dated dir".into())
-                        .unwrap());
+        (&Method::GET, path) if path.starts_with("/media/") => {
+            let video_id = path.trim_start_matches("/media/");
+            let catalog = state.lock().await.catalog.clone();
+            let media_path = catalog
+                .lock()
+                .await
+                .get(video_id)
+                .and_then(|entry| entry.media_path())
+                .map(PathBuf::from);
+            match media_path {
+                Some(media_path) => {
+                    let range = req
+                        .headers()
+                        .get(hyper::header::RANGE)
+                        .and_then(|range| range.to_str().ok());
+                    Ok(media::serve_file(&media_path, range).await)
                 }
+                None => Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("No local copy of this video".into())
+                    .unwrap()),
+            }
+        }
+        (&Method::GET, "/transcripts/search") => {
+            let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            let query = query_map.get("q").map(|q| q.trim()).unwrap_or_default();
+            let limit = query_map
+                .get("limit")
+                .and_then(|limit| limit.parse::<usize>().ok())
+                .unwrap_or(20);
+            if query.is_empty() {
+                Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("Missing search query q".into())
+                    .unwrap())
+            } else {
+                let (transcript_index, catalog) = {
+                    let dastate = state.lock().await;
+                    (dastate.transcript_index.clone(), dastate.catalog.clone())
+                };
+                transcript_index::refresh(&transcript_index, &catalog).await;
+                let hits = transcript_index.lock().await.search(query, limit);
+                Ok(Response::new(serde_json::to_string(&hits).unwrap().into()))
+            }
+        }
+        (&Method::GET, "/tags") => {
+            let notes_index = state.lock().await.notes_index.clone();
+            let mut tags = notes_index
+                .lock()
+                .await
+                .tags()
+                .map(|(tag, count)| TagCount {
+                    tag: tag.to_string(),
+                    count,
+                })
+                .collect::<Vec<TagCount>>();
+            tags.sort_by_key(|tag| std::cmp::Reverse(tag.count));
+            Ok(Response::new(serde_json::to_string(&tags).unwrap().into()))
+        }
+        (&Method::POST, "/tags/rename") => Ok(handle_tag_rename(req, state).await),
+        (&Method::GET, path) if path.starts_with("/tags/") => {
+            let tag = path.trim_start_matches("/tags/").to_string();
+            Ok(handle_tagged_notes(state, &tag).await)
+        }
+        (&Method::GET, "/notes") => Ok(handle_notes_list(req, state).await),
+        (&Method::GET, "/graph") => Ok(handle_graph(req, state).await),
+        (&Method::GET, path) if path.starts_with("/notes/") && path.ends_with("/backlinks") => {
+            let id = path
+                .trim_start_matches("/notes/")
+                .trim_end_matches("/backlinks")
+                .to_string();
+            Ok(handle_backlinks(state, &id).await)
+        }
+        (&Method::GET, "/notes/search") => Ok(handle_notes_search(req, state).await),
+        (&Method::GET, path) if path.starts_with("/annotated/") => {
+            let video_id = path.trim_start_matches("/annotated/").to_string();
+            Ok(handle_annotated(req, state, &video_id).await)
+        }
+        (&Method::GET, path) if path.starts_with("/transcript/") => {
+            let video_id = path.trim_start_matches("/transcript/").to_string();
+            Ok(handle_transcript(req, state, &video_id).await)
+        }
+        (&Method::GET, path) if path.starts_with("/player/") => {
+            let video_id = path.trim_start_matches("/player/").to_string();
+            let (catalog, notes_map, notes_dir, metadata) = {
+                let dastate = state.lock().await;
+                (
+                    dastate.catalog.clone(),
+                    dastate.notes_map.clone(),
+                    dastate.config.notes_dir.clone(),
+                    dastate.metadata.clone(),
+                )
             };
-
-            // Construct and run the yt-dlp command to download just subtitles.
-            // This command uses: yt-dlp URL --write-auto-sub --skip-download --sub-lang en
-            let output = std::process::Command::new("pwsh")
-                .current_dir(&dated_dir)
-                .arg("-NoProfile")
-                .arg("-WorkingDirectory")
-                .arg("$(Get-Location)")
-                .arg("-c")
-                .arg(format!(
-                    "wt pwsh.exe -NoProfile -WorkingDirectory $(Get-Location) -c 'yt-dlp \"{}\" --write-auto-sub --skip-download --sub-lang en && Write-Host \"\"press any key to close\"\" && $Host.UI.RawUI.ReadKey(\"\"NoEcho,IncludeKeyDown\"\")'",
-                    url
-                ))
-                .output()
-                .expect("Failed to execute command");
-
-            // Return a response based on whether the command succeeded.
-            let res = if output.status.success() {
-                Response::new("Subtitles download started".into())
+            let entry = catalog.lock().await.get(&video_id).cloned();
+            if entry
+                .as_ref()
+                .and_then(|entry| entry.media_path())
+                .is_none()
+            {
+                Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("No local copy of this video".into())
+                    .unwrap())
             } else {
+                let mut title = entry.and_then(|entry| entry.title);
+                if title.is_none() {
+                    title = metadata.get(&video_id).await.map(|metadata| metadata.title);
+                }
+                let title = title.unwrap_or_else(|| video_id.clone());
+
+                // Keep writing to the note the video already has, if any
+                let note = {
+                    let video_id = video_id.clone();
+                    tokio::task::spawn_blocking(move || notes::find_note(&notes_dir, &video_id))
+                        .await
+                        .unwrap_or_default()
+                };
+                let note_id = match note
+                    .as_ref()
+                    .and_then(|note| Some((note, note.file_stem()?)))
+                {
+                    Some((note, stem)) => {
+                        let note_id = stem.to_string_lossy().to_string();
+                        notes_map
+                            .lock()
+                            .await
+                            .insert(note_id.clone(), note.display().to_string());
+                        note_id
+                    }
+                    None => format!(
+                        "[{}] [youtube] [{}] {}",
+                        Local::now().format("%Y-%m-%d"),
+                        video_id,
+                        title
+                    ),
+                };
+                Ok(Response::builder()
+                    .header("Content-Type", "text/html; charset=utf-8")
+                    .body(media::player_page(&video_id, &note_id, &title).into())
+                    .unwrap())
+            }
+        }
+        (&Method::GET, path) if path.starts_with("/metadata/") => {
+            let video_id = path.trim_start_matches("/metadata/");
+            let query_map = url::form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            let refresh = query_map.get("refresh").is_some_and(|v| v == "true");
+
+            let (metadata, downloader) = {
+                let dastate = state.lock().await;
+                (dastate.metadata.clone(), dastate.downloader.clone())
+            };
+            let res = if !youtube::is_video_id(video_id) {
                 Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body("Subtitles download failed".into())
+                    .status(StatusCode::BAD_REQUEST)
+                    .body("Invalid video id".into())
                     .unwrap()
+            } else {
+                match metadata
+                    .get_or_fetch(downloader.as_ref(), video_id, refresh)
+                    .await
+                {
+                    Ok(metadata) => Response::builder()
+                        .header("Content-Type", "application/json")
+                        .body(serde_json::to_string(&metadata).unwrap().into())
+                        .unwrap(),
+                    Err(err) => {
+                        error!("Error fetching metadata for {}: {}", video_id, err);
+                        Response::builder()
+                            .status(StatusCode::BAD_GATEWAY)
+                            .body("Error fetching metadata".into())
+                            .unwrap()
+                    }
+                }
             };
-
             Ok(res)
         }
+        (&Method::GET, "/catalog") => {
+            let catalog = state.lock().await.catalog.clone();
+            let catalog = catalog.lock().await;
+            let entries = catalog.entries().collect::<Vec<_>>();
+            Ok(Response::new(
+                serde_json::to_string(&entries).unwrap().into(),
+            ))
+        }
+        (&Method::POST, "/catalog/rescan") => {
+            let (catalog, file_search, search_dirs) = {
+                let dastate = state.lock().await;
+                (
+                    dastate.catalog.clone(),
+                    dastate.file_search.clone(),
+                    dastate.config.search_dirs.clone(),
+                )
+            };
+            match catalog::rescan(&catalog, file_search, search_dirs).await {
+                Ok(found) => Ok(Response::new(format!("Found {} files", found).into())),
+                Err(err) => {
+                    error!("Error scanning search dirs: {}", err);
+                    Ok(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body("Error scanning search dirs".into())
+                        .unwrap())
+                }
+            }
+        }
+        (&Method::GET, path) if path.starts_with("/catalog/") => {
+            let video_id = path.trim_start_matches("/catalog/");
+            let catalog = state.lock().await.catalog.clone();
+            let catalog = catalog.lock().await;
+            match catalog.get(video_id) {
+                Some(entry) => Ok(Response::new(serde_json::to_string(entry).unwrap().into())),
+                None => Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("Not in catalog".into())
+                    .unwrap()),
+            }
+        }
+        (_, path) if path == "/subscriptions" || path.starts_with("/subscriptions/") => {
+            let (subscriptions, downloads, settings, downloads_dir) = {
+                let dastate = state.lock().await;
+                (
+                    dastate.subscriptions.clone(),
+                    dastate.download_context(),
+                    dastate.settings.clone(),
+                    dastate.config.downloads_dir.clone(),
+                )
+            };
+            Ok(
+                subscriptions::handle(req, subscriptions, downloads, &settings, downloads_dir)
+                    .await,
+            )
+        }
+        (&Method::GET, path) if path == "/jobs" || path.starts_with("/jobs/") => {
+            let jobs = state.lock().await.jobs.clone();
+            Ok(jobs::handle_get(path, req.uri().query(), &jobs).await)
+        }
 
         _ => {
             let mut not_found = Response::default();
@@ -717,7 +2386,7 @@ async fn handle(
     );
     headers.insert(
         "Access-Control-Allow-Methods",
-        "GET, POST, OPTIONS".parse().unwrap(),
+        "GET, POST, DELETE, OPTIONS".parse().unwrap(),
     );
     headers.insert(
         "Access-Control-Allow-Headers",
@@ -0,0 +1,252 @@
+use hyper::body::Bytes;
+use hyper::Body;
+use hyper::Response;
+use hyper::StatusCode;
+use regex::Regex;
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tracing::error;
+use tracing::warn;
+
+/// How much of a file is read per chunk sent.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+pub fn content_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match ext.as_str() {
+        "mp4" | "m4v" => "video/mp4",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "flv" => "video/x-flv",
+        "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "opus" | "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "aac" => "audio/aac",
+        "vtt" => "text/vtt; charset=utf-8",
+        "srt" => "application/x-subrip",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "json" => "application/json",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Range {
+    Whole,
+    /// Inclusive byte offsets.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Parses a `Range` header for a file of `len` bytes. Anything but a single
+/// byte range is answered with the whole file, which the spec allows.
+fn parse_range(header: Option<&str>, len: u64) -> Range {
+    let Some(spec) = header.and_then(|header| header.trim().strip_prefix("bytes=")) else {
+        return Range::Whole;
+    };
+    if spec.contains(',') {
+        return Range::Whole;
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Range::Whole;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // bytes=-500 is the last 500 bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return Range::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        _ => return Range::Whole,
+    };
+    if len == 0 || range.0 >= len {
+        Range::Unsatisfiable
+    } else {
+        Range::Partial(range.0, range.1)
+    }
+}
+
+/// Streams `path`, honouring a single-range `Range` header so players can
+/// seek.
+pub async fn serve_file(path: &Path, range_header: Option<&str>) -> Response<Body> {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(it) => it,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("File no longer exists".into())
+                .unwrap();
+        }
+        Err(err) => {
+            error!("Error opening {}: {}", path.display(), err);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error opening file".into())
+                .unwrap();
+        }
+    };
+    let len = match file.metadata().await {
+        Ok(metadata) => metadata.len(),
+        Err(err) => {
+            error!("Error reading metadata of {}: {}", path.display(), err);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body("Error reading file".into())
+                .unwrap();
+        }
+    };
+
+    let builder = Response::builder()
+        .header("Content-Type", content_type(path))
+        .header("Accept-Ranges", "bytes");
+    let (builder, start, count) = match parse_range(range_header, len) {
+        Range::Whole => (builder.status(StatusCode::OK), 0, len),
+        Range::Partial(start, end) => (
+            builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header("Content-Range", format!("bytes {}-{}/{}", start, end, len)),
+            start,
+            end - start + 1,
+        ),
+        Range::Unsatisfiable => {
+            return builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Range", format!("bytes */{}", len))
+                .body(Body::empty())
+                .unwrap();
+        }
+    };
+    if let Err(err) = file.seek(std::io::SeekFrom::Start(start)).await {
+        error!("Error seeking in {}: {}", path.display(), err);
+        return Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body("Error reading file".into())
+            .unwrap();
+    }
+
+    let (mut sender, body) = Body::channel();
+    let path_text = path.display().to_string();
+    tokio::spawn(async move {
+        let mut remaining = count;
+        let mut buf = vec![0; CHUNK_BYTES];
+        while remaining > 0 {
+            let want = buf.len().min(remaining as usize);
+            let read = match file.read(&mut buf[..want]).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) => {
+                    warn!("Error reading {}: {}", path_text, err);
+                    sender.abort();
+                    return;
+                }
+            };
+            remaining -= read as u64;
+            // Fails when the client goes away, e.g. after seeking elsewhere
+            if sender
+                .send_data(Bytes::copy_from_slice(&buf[..read]))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    });
+    builder.header("Content-Length", count).body(body).unwrap()
+}
+
+/// The built-in player: the local copy of a video next to its notes, for
+/// taking notes without YouTube.
+pub fn player_page(video_id: &str, note_id: &str, title: &str) -> String {
+    // One pass over the page, so placeholders inside values stay as they are
+    placeholder_regex()
+        .replace_all(
+            include_str!("player.html"),
+            |captures: &regex::Captures| match &captures[1] {
+                "title" => html_escape(title),
+                "video_id_json" => script_json(video_id),
+                "note_id_json" => script_json(note_id),
+                _ => captures[0].to_string(),
+            },
+        )
+        .into_owned()
+}
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{\{(\w+)\}\}").unwrap())
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A JSON string literal that is also safe inside a `<script>` element.
+fn script_json(text: &str) -> String {
+    serde_json::to_string(text).unwrap().replace("</", "<\\/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        let range = |header| parse_range(Some(header), 1000);
+        assert_eq!(parse_range(None, 1000), Range::Whole);
+        assert_eq!(range("bytes=0-499"), Range::Partial(0, 499));
+        assert_eq!(range(" bytes=500- "), Range::Partial(500, 999));
+        // The last 300 bytes, or all of them when asking for more
+        assert_eq!(range("bytes=-300"), Range::Partial(700, 999));
+        assert_eq!(range("bytes=-5000"), Range::Partial(0, 999));
+        // Ends past the file are clamped
+        assert_eq!(range("bytes=900-5000"), Range::Partial(900, 999));
+        assert_eq!(range("bytes=999-999"), Range::Partial(999, 999));
+    }
+
+    #[test]
+    fn answers_other_ranges_with_whole_file_or_unsatisfiable() {
+        let range = |header| parse_range(Some(header), 1000);
+        assert_eq!(range("bytes=0-1,5-9"), Range::Whole);
+        assert_eq!(range("bytes=9-5"), Range::Whole);
+        assert_eq!(range("bytes=abc"), Range::Whole);
+        assert_eq!(range("items=0-5"), Range::Whole);
+        assert_eq!(range("bytes=-"), Range::Whole);
+        assert_eq!(range("bytes=1000-"), Range::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), Range::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=0-"), 0), Range::Unsatisfiable);
+        assert_eq!(parse_range(Some("bytes=-10"), 0), Range::Unsatisfiable);
+    }
+
+    #[test]
+    fn player_page_fills_placeholders_once() {
+        let page = player_page(
+            "aaaaaaaaaaa",
+            "youtube/{{title}} <b> (aaaaaaaaaaa)",
+            "{{note_id_json}} </script>",
+        );
+        assert!(page.contains("<title>{{note_id_json}} &lt;/script&gt;</title>"));
+        assert!(page.contains(r#"const videoId = "aaaaaaaaaaa";"#));
+        assert!(page.contains(r#"const noteId = "youtube/{{title}} <b> (aaaaaaaaaaa)";"#));
+        assert!(!page.contains("</script> "));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::downloader::Downloader;
+use crate::youtube::is_video_id;
+use crate::youtube::YoutubeUrl;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use std::path::PathBuf;
+use tracing::info;
+use tracing::warn;
+
+/// The parts of yt-dlp's `--dump-json` output we keep.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct VideoMetadata {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub channel_id: Option<String>,
+    #[serde(default)]
+    pub channel_url: Option<String>,
+    #[serde(default)]
+    pub uploader: Option<String>,
+    /// In seconds.
+    #[serde(default)]
+    pub duration: Option<f64>,
+    /// `YYYYMMDD`, as yt-dlp reports it.
+    #[serde(default)]
+    pub upload_date: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub chapters: Vec<Chapter>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub webpage_url: Option<String>,
+    #[serde(default)]
+    pub thumbnail: Option<String>,
+    #[serde(default)]
+    pub view_count: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Chapter {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub title: String,
+}
+
+/// yt-dlp's `--flat-playlist --dump-single-json` output for a playlist.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlaylistInfo {
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default, deserialize_with = "null_as_default")]
+    pub entries: Vec<PlaylistEntry>,
+}
+
+/// A flat playlist entry, which only carries what the playlist page shows.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlaylistEntry {
+    pub id: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub duration: Option<f64>,
+}
+
+impl VideoMetadata {
+    pub fn channel_name(&self) -> Option<&str> {
+        self.channel.as_deref().or(self.uploader.as_deref())
+    }
+
+    /// `upload_date` as `YYYY-MM-DD`.
+    pub fn upload_date_iso(&self) -> Option<String> {
+        let date = self.upload_date.as_deref()?;
+        if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+    }
+}
+
+/// yt-dlp writes `null` rather than `[]` for videos without chapters or tags.
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// One JSON file per video id under the data dir, filled on demand.
+pub struct MetadataCache {
+    dir: PathBuf,
+}
+
+impl MetadataCache {
+    pub fn new(dir: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(MetadataCache { dir })
+    }
+
+    fn path(&self, video_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", video_id))
+    }
+
+    /// The cached metadata, without fetching anything.
+    pub async fn get(&self, video_id: &str) -> Option<VideoMetadata> {
+        if !is_video_id(video_id) {
+            return None;
+        }
+        let path = self.path(video_id);
+        let content = tokio::fs::read_to_string(&path).await.ok()?;
+        match serde_json::from_str(&content) {
+            Ok(metadata) => Some(metadata),
+            Err(err) => {
+                warn!("Ignoring unreadable {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    /// The cached metadata, fetching and caching it first if it is missing
+    /// or `refresh` is set.
+    pub async fn get_or_fetch(
+        &self,
+        downloader: &dyn Downloader,
+        video_id: &str,
+        refresh: bool,
+    ) -> Result<VideoMetadata, String> {
+        if !is_video_id(video_id) {
+            return Err(format!("Invalid video id: {:?}", video_id));
+        }
+        if !refresh {
+            if let Some(metadata) = self.get(video_id).await {
+                return Ok(metadata);
+            }
+        }
+        info!("Fetching metadata for {}", video_id);
+        let url = YoutubeUrl::Video {
+            id: video_id.to_string(),
+        };
+        let metadata = downloader.metadata(&url).await?;
+        let path = self.path(video_id);
+        tokio::fs::write(&path, serde_json::to_string_pretty(&metadata).unwrap())
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(metadata)
+    }
+}
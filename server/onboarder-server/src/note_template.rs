@@ -0,0 +1,216 @@
+use crate::metadata::VideoMetadata;
+use crate::timestamps::deep_link;
+use crate::timestamps::format_timestamp;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+/// Templates that pre-fill new notes, from the `note_templates` section of
+/// the settings file. With nothing configured, new notes start empty.
+///
+/// A template is plain text where `{title}`, `{url}`, `{video_id}`,
+/// `{channel}`, `{upload_date}`, `{duration}`, `{tags}`, `{chapters}` and
+/// `{links}` are replaced with values from the video's metadata.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct NoteTemplates {
+    /// Used when neither the channel nor the source has a template.
+    pub default: Option<String>,
+    /// Keyed by the source in the note id, e.g. `youtube`.
+    pub by_source: BTreeMap<String, String>,
+    /// Keyed by channel name or channel id. Takes precedence over `by_source`.
+    pub by_channel: BTreeMap<String, String>,
+}
+
+impl NoteTemplates {
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.by_source.is_empty() && self.by_channel.is_empty()
+    }
+
+    /// The most specific template for a note.
+    pub fn select(&self, source: Option<&str>, metadata: &VideoMetadata) -> Option<&str> {
+        let by_channel = [metadata.channel_name(), metadata.channel_id.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.by_channel.get(key));
+        let by_source = source.and_then(|source| self.by_source.get(source));
+        by_channel
+            .or(by_source)
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Fills in a template. Values that are missing from the metadata become
+/// empty strings.
+pub fn render(template: &str, metadata: &VideoMetadata) -> String {
+    let url = metadata
+        .webpage_url
+        .clone()
+        .unwrap_or_else(|| format!("https://www.youtube.com/watch?v={}", metadata.id));
+    let value = |name: &str| match name {
+        "title" => Some(metadata.title.clone()),
+        "url" => Some(url.clone()),
+        "video_id" => Some(metadata.id.clone()),
+        "channel" => Some(metadata.channel_name().unwrap_or_default().to_string()),
+        "upload_date" => Some(metadata.upload_date_iso().unwrap_or_default()),
+        "duration" => Some(metadata.duration.map(format_timestamp).unwrap_or_default()),
+        "tags" => Some(metadata.tags.join(", ")),
+        "chapters" => Some(chapter_outline(metadata)),
+        "links" => Some(description_links(metadata)),
+        _ => None,
+    };
+    // One pass over the template, so placeholders inside values stay as they are
+    placeholder_regex()
+        .replace_all(template, |captures: &regex::Captures| {
+            value(&captures[1]).unwrap_or_else(|| captures[0].to_string())
+        })
+        .into_owned()
+}
+
+fn placeholder_regex() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{(\w+)\}").unwrap())
+}
+
+/// One Markdown list item per chapter, each linking to where it starts.
+fn chapter_outline(metadata: &VideoMetadata) -> String {
+    metadata
+        .chapters
+        .iter()
+        .map(|chapter| {
+            format!(
+                "- [{}]({}) {}",
+                format_timestamp(chapter.start_time),
+                deep_link(&metadata.id, chapter.start_time),
+                chapter.title
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Lines `{chapters}` and `{links}` fill in, which link to the video itself
+/// and to whatever its description mentions rather than to anything the
+/// user wrote.
+pub fn generated_lines(metadata: &VideoMetadata) -> Vec<String> {
+    [chapter_outline(metadata), description_links(metadata)]
+        .iter()
+        .flat_map(|section| section.lines())
+        .map(str::to_string)
+        .collect()
+}
+
+/// One Markdown list item per distinct link in the description.
+fn description_links(metadata: &VideoMetadata) -> String {
+    let mut links: Vec<&str> = Vec::new();
+    for word in metadata
+        .description
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+    {
+        if !word.starts_with("http://") && !word.starts_with("https://") {
+            continue;
+        }
+        let link = word.trim_end_matches(['.', ',', ';', ':', ')', ']', '!', '?']);
+        if !links.contains(&link) {
+            links.push(link);
+        }
+    }
+    links
+        .iter()
+        .map(|link| format!("- {}", link))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::links;
+    use crate::links::LinkTarget;
+
+    fn metadata(title: &str, description: &str) -> VideoMetadata {
+        serde_json::from_value(serde_json::json!({
+            "id": "dQw4w9WgXcQ",
+            "title": title,
+            "channel": "Channel",
+            "description": description,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn fills_in_placeholders() {
+        let metadata = metadata("Title", "see https://example.com/a.");
+        assert_eq!(
+            render("# {title}\n{url} by {channel}\n{links}", &metadata),
+            "# Title\nhttps://www.youtube.com/watch?v=dQw4w9WgXcQ by Channel\n- https://example.com/a"
+        );
+    }
+
+    #[test]
+    fn leaves_placeholders_in_values_alone() {
+        let metadata = metadata("How {links} and {url} work", "{title} https://example.com");
+        assert_eq!(
+            render("{title}\n{links}", &metadata),
+            "How {links} and {url} work\n- https://example.com"
+        );
+    }
+
+    #[test]
+    fn generated_lines_are_what_the_template_fills_in() {
+        let metadata = metadata(
+            "Title",
+            "more at https://youtu.be/bbbbbbbbbbb, and https://example.com",
+        );
+        let note = render(
+            "{url}\n{links}\nmy own https://youtu.be/bbbbbbbbbbb",
+            &metadata,
+        );
+        let generated = generated_lines(&metadata);
+        let kept = note
+            .lines()
+            .filter(|line| !generated.iter().any(|generated| generated == line))
+            .collect::<Vec<&str>>();
+        assert_eq!(
+            kept,
+            vec![
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                "my own https://youtu.be/bbbbbbbbbbb"
+            ]
+        );
+    }
+
+    #[test]
+    fn own_links_after_generated_ones_are_kept() {
+        let metadata = metadata("Title", "see https://youtu.be/bbbbbbbbbbb");
+        let note = render(
+            "{links}\n\nmy own thought, see https://youtu.be/bbbbbbbbbbb",
+            &metadata,
+        );
+        assert_eq!(
+            note,
+            "- https://youtu.be/bbbbbbbbbbb\n\nmy own thought, see https://youtu.be/bbbbbbbbbbb"
+        );
+        let links = links::extract(&note, &generated_lines(&metadata));
+        assert_eq!(links.len(), 1);
+        assert_eq!(
+            links[0].target,
+            LinkTarget::Video("bbbbbbbbbbb".to_string())
+        );
+        assert_eq!(
+            links[0].context,
+            "my own thought, see https://youtu.be/bbbbbbbbbbb"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders() {
+        let metadata = metadata("Title", "");
+        assert_eq!(render("{nope} {title}", &metadata), "{nope} Title");
+    }
+}
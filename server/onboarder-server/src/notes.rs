@@ -0,0 +1,246 @@
+use crate::catalog::FileKind;
+use crate::youtube::is_video_id;
+use chrono::DateTime;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// Pulls the video id out of a note id composed by the extension, which
+/// looks like `[2024-01-31] [youtube] [dQw4w9WgXcQ] Some title`.
+pub fn video_id_from_note_id(id: &str) -> Option<&str> {
+    let rest = id.split_once("[youtube] [")?.1;
+    let video_id = &rest[..rest.find(']')?];
+    is_video_id(video_id).then_some(video_id)
+}
+
+/// The source part of a note id, e.g. `youtube` for the id above.
+pub fn source_from_note_id(id: &str) -> Option<&str> {
+    let rest = id.trim_start().strip_prefix('[')?;
+    let rest = rest[rest.find(']')? + 1..].trim_start().strip_prefix('[')?;
+    Some(&rest[..rest.find(']')?])
+}
+
+/// The title part of a note id, after the bracketed date, source and id.
+pub fn title_from_note_id(id: &str) -> &str {
+    let mut rest = id.trim_start();
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(end) = inner.find(']') else {
+            break;
+        };
+        rest = inner[end + 1..].trim_start();
+    }
+    rest.trim_end()
+}
+
+/// Identifies a version of a note's text, so a save made on top of an
+/// older version can be told apart from one made on top of the file. This
+/// is 64-bit FNV-1a, which unlike the standard library's hasher stays the
+/// same across builds, so open tabs keep matching after an upgrade.
+pub fn version(content: &str) -> String {
+    let hash = content
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    format!("{:016x}", hash)
+}
+
+/// Replaces the content of the note at `path`. The new content is written
+/// to a temporary file first and then copied over the note, rather than
+/// renamed over it, so the note keeps its created time. Should copying
+/// fail, the temporary file is left next to the note so nothing is lost.
+pub fn rewrite(path: &Path, content: &str) -> Result<(), String> {
+    let tmp = path.with_extension("txt.tmp");
+    std::fs::write(&tmp, content)
+        .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    std::fs::copy(&tmp, path).map_err(|e| {
+        format!(
+            "Failed to write {}, its new content is in {}: {}",
+            path.display(),
+            tmp.display(),
+            e
+        )
+    })?;
+    if let Err(err) = std::fs::remove_file(&tmp) {
+        warn!("Failed to remove {}: {}", tmp.display(), err);
+    }
+    Ok(())
+}
+
+/// Every note file under `notes_dir`, in no particular order.
+pub fn note_files(notes_dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![notes_dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(file_type)
+                    if file_type.is_file() && path.extension().is_some_and(|ext| ext == "txt") =>
+                {
+                    files.push(path)
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// The most recently modified note about a video. Note files are named after
+/// their id, so they carry the same `[youtube] [ID]` marker.
+pub fn find_note(notes_dir: &Path, video_id: &str) -> Option<PathBuf> {
+    let marker = format!("[youtube] [{}]", video_id);
+    note_files(notes_dir)
+        .into_iter()
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.contains(&marker))
+        })
+        .max_by_key(|path| {
+            std::fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        })
+}
+
+/// What the server knows about a note besides its text, kept next to it as
+/// `<note>.meta.json`. The extension saves notes whole, so anything the
+/// server wrote into the note itself could be overwritten by the next save.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct NoteMeta {
+    /// Files downloaded for the note's video, oldest first.
+    pub downloads: Vec<NoteDownload>,
+    /// Clips cut from the video, oldest first.
+    pub clips: Vec<NoteClip>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteDownload {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub recorded: DateTime<Local>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NoteClip {
+    pub path: PathBuf,
+    /// In seconds.
+    pub start: f64,
+    /// In seconds.
+    pub end: f64,
+    pub recorded: DateTime<Local>,
+}
+
+impl NoteMeta {
+    pub fn path(note: &Path) -> PathBuf {
+        note.with_extension("meta.json")
+    }
+
+    /// The metadata of `note`, empty if there is none yet.
+    pub fn load(note: &Path) -> NoteMeta {
+        let path = NoteMeta::path(note);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return NoteMeta::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|err| {
+            warn!("Ignoring unreadable {}: {}", path.display(), err);
+            NoteMeta::default()
+        })
+    }
+
+    pub fn save(&self, note: &Path) -> Result<(), String> {
+        let path = NoteMeta::path(note);
+        std::fs::write(&path, serde_json::to_string_pretty(self).unwrap())
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Records `files`, replacing earlier records of the same paths. Files
+    /// that aren't media, subtitles or info json are left out.
+    pub fn record_downloads(&mut self, files: &[PathBuf]) {
+        for file in files {
+            let Some(kind) = FileKind::from_path(file) else {
+                continue;
+            };
+            self.downloads.retain(|download| &download.path != file);
+            self.downloads.push(NoteDownload {
+                path: file.clone(),
+                kind,
+                recorded: Local::now(),
+            });
+        }
+    }
+
+    /// The latest download still on disk, preferring video over audio.
+    pub fn media_path(&self) -> Option<&Path> {
+        [FileKind::Video, FileKind::Audio]
+            .into_iter()
+            .find_map(|kind| {
+                self.downloads
+                    .iter()
+                    .rev()
+                    .find(|download| download.kind == kind && download.path.exists())
+            })
+            .map(|download| download.path.as_path())
+    }
+}
+
+/// Applies `update` to the metadata of the video's note, if it has one,
+/// and returns that note.
+pub fn update_meta(
+    notes_dir: &Path,
+    video_id: &str,
+    update: impl FnOnce(&mut NoteMeta),
+) -> Result<Option<PathBuf>, String> {
+    let Some(note) = find_note(notes_dir, video_id) else {
+        return Ok(None);
+    };
+    let mut meta = NoteMeta::load(&note);
+    update(&mut meta);
+    meta.save(&note)?;
+    Ok(Some(note))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_stable() {
+        // Published FNV-1a test vectors
+        assert_eq!(version(""), "cbf29ce484222325");
+        assert_eq!(version("a"), "af63dc4c8601ec8c");
+        assert_eq!(version("foobar"), "85944171f73967e8");
+    }
+
+    #[test]
+    fn rewrite_keeps_the_file() {
+        let dir = std::env::temp_dir().join(format!("onboarder-notes-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("note.txt");
+        std::fs::write(&path, "#old tag and a longer text").unwrap();
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&std::fs::metadata(&path).unwrap());
+
+        rewrite(&path, "#new tag").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "#new tag");
+        assert!(!path.with_extension("txt.tmp").exists());
+        #[cfg(unix)]
+        assert_eq!(
+            std::os::unix::fs::MetadataExt::ino(&std::fs::metadata(&path).unwrap()),
+            inode
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1046 @@
+use crate::links;
+use crate::links::LinkTarget;
+use crate::links::NoteLink;
+use crate::metadata::MetadataCache;
+use crate::note_template;
+use crate::notes;
+use crate::ranking;
+use crate::ranking::Token;
+use crate::tags;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::NaiveDate;
+use notify::RecursiveMode;
+use notify::Watcher;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+/// How long to wait for more changes before indexing, as the extension
+/// saves on every keystroke.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+/// Index anyway after this long, for notes that are being typed in nonstop.
+const MAX_DELAY: Duration = Duration::from_secs(10);
+/// How often changes to the index are written out. Whatever changed since
+/// the last save is indexed again at startup, so this only bounds that work.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+/// Words of context on each side of a match in a snippet.
+const SNIPPET_WORDS: usize = 8;
+const MAX_SNIPPETS: usize = 2;
+/// Bumped when notes are indexed differently, to index them all again.
+const VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct IndexedNote {
+    /// The note id, which is its file name without `.txt`.
+    pub id: String,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub created: Option<DateTime<Local>>,
+    pub modified: Option<DateTime<Local>>,
+    pub size: u64,
+    /// From the `[YYYY-MM-DD]` the extension puts in note ids, or failing
+    /// that the dated folder the note is in.
+    pub date: Option<NaiveDate>,
+    pub video_id: Option<String>,
+    /// From the cached metadata of the video.
+    pub channel: Option<String>,
+    pub tags: Vec<String>,
+    /// In words, for ranking.
+    pub length: u32,
+    #[serde(default)]
+    pub links: Vec<NoteLink>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    /// [`VERSION`] when saved, as older indexes lack what was added since.
+    #[serde(default)]
+    version: u32,
+    notes: BTreeMap<String, IndexedNote>,
+    /// Word to note key to the word's positions in the note.
+    postings: BTreeMap<String, BTreeMap<String, Vec<u32>>>,
+}
+
+impl Stored {
+    fn empty() -> Self {
+        Stored {
+            version: VERSION,
+            ..Default::default()
+        }
+    }
+}
+
+/// An inverted index over every note under `notes_dir`, keyed by the
+/// note's path relative to it and persisted as JSON in the data dir.
+pub struct NotesIndex {
+    path: PathBuf,
+    notes_dir: PathBuf,
+    stored: Stored,
+    /// Tag to the keys of the notes that have it, built from `stored`.
+    tags: BTreeMap<String, BTreeSet<String>>,
+    /// Note key to the words in `stored.postings` it has, built from
+    /// `stored`, so forgetting a note only touches its own words.
+    terms: HashMap<String, Vec<String>>,
+    dirty: bool,
+}
+
+/// Parts of a `/notes/search` query.
+#[derive(Debug, PartialEq)]
+enum Clause {
+    Word(String),
+    /// `word*`
+    Prefix(String),
+    /// `"some words"`, in order.
+    Phrase(Vec<String>),
+}
+
+fn parse_query(query: &str) -> Vec<Clause> {
+    let mut clauses = Vec::new();
+    let mut add = |text: &str, quoted: bool| {
+        let prefix = !quoted && text.ends_with('*');
+        let words = ranking::words(text);
+        match words.as_slice() {
+            [] => {}
+            [word] if prefix => clauses.push(Clause::Prefix(word.clone())),
+            [word] => clauses.push(Clause::Word(word.clone())),
+            _ => clauses.push(Clause::Phrase(words)),
+        }
+    };
+    for (index, part) in query.split('"').enumerate() {
+        // Odd parts were between quotes
+        if index % 2 == 1 {
+            add(part, true);
+        } else {
+            part.split_whitespace().for_each(|word| add(word, false));
+        }
+    }
+    clauses
+}
+
+/// What `/notes/search` narrows results down by besides the query.
+#[derive(Debug, Default)]
+pub struct Filters {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    pub channel: Option<String>,
+    pub tag: Option<String>,
+}
+
+impl Filters {
+    pub fn matches(&self, note: &IndexedNote) -> bool {
+        let date_ok = |bound: Option<NaiveDate>, ok: fn(NaiveDate, NaiveDate) -> bool| {
+            bound.is_none_or(|bound| note.date.is_some_and(|date| ok(date, bound)))
+        };
+        date_ok(self.from, |date, from| date >= from)
+            && date_ok(self.to, |date, to| date <= to)
+            && self.channel.as_ref().is_none_or(|channel| {
+                note.channel
+                    .as_ref()
+                    .is_some_and(|note_channel| note_channel.eq_ignore_ascii_case(channel))
+            })
+            && self
+                .tag
+                .as_ref()
+                .is_none_or(|tag| note.tags.contains(&tags::normalize(tag)))
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct NoteHit {
+    #[serde(flatten)]
+    pub note: IndexedNote,
+    pub score: f64,
+    /// Bits of the note around the matches, which are wrapped in `<mark>`.
+    /// The rest is HTML-escaped.
+    pub snippets: Vec<String>,
+    /// Positions of the matched words, for [`snippets`].
+    #[serde(skip)]
+    pub positions: Vec<u32>,
+}
+
+/// Finds the notes links point at, with lookups built once for all links.
+struct Resolver<'a> {
+    by_title: HashMap<String, &'a IndexedNote>,
+    by_video: HashMap<&'a str, &'a IndexedNote>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(index: &'a NotesIndex) -> Self {
+        let mut newest_first = index.notes().collect::<Vec<&IndexedNote>>();
+        newest_first.sort_by_key(|note| std::cmp::Reverse((note.date, note.modified)));
+        let mut by_title = HashMap::new();
+        let mut by_video = HashMap::new();
+        for note in newest_first {
+            let title = notes::title_from_note_id(&note.id);
+            for name in [note.id.as_str(), title] {
+                by_title.entry(links::normalize_title(name)).or_insert(note);
+            }
+            if let Some(video_id) = &note.video_id {
+                by_video.entry(video_id.as_str()).or_insert(note);
+            }
+        }
+        Resolver { by_title, by_video }
+    }
+
+    fn resolve(&self, target: &LinkTarget) -> Option<&'a IndexedNote> {
+        match target {
+            LinkTarget::Video(video_id) => self.by_video.get(video_id.as_str()),
+            LinkTarget::Title(title) => self
+                .by_title
+                .get(&links::normalize_title(title))
+                .or_else(|| self.by_video.get(title.trim())),
+        }
+        .copied()
+    }
+}
+
+impl NotesIndex {
+    /// The index saved at `path`, or an empty one if there is none, it
+    /// can't be read or it is from an older version, in which case
+    /// everything is indexed again.
+    pub fn load(path: PathBuf, notes_dir: PathBuf) -> Self {
+        let stored = match std::fs::read_to_string(&path) {
+            Ok(content) => match serde_json::from_str::<Stored>(&content) {
+                Ok(stored) if stored.version == VERSION => stored,
+                Ok(_) => {
+                    info!("Rebuilding {} from an older version", path.display());
+                    Stored::empty()
+                }
+                Err(err) => {
+                    warn!("Rebuilding unreadable {}: {}", path.display(), err);
+                    Stored::empty()
+                }
+            },
+            Err(_) => Stored::empty(),
+        };
+        let mut index = NotesIndex {
+            path,
+            notes_dir,
+            stored,
+            tags: BTreeMap::new(),
+            terms: HashMap::new(),
+            dirty: false,
+        };
+        for (key, note) in &index.stored.notes {
+            for tag in &note.tags {
+                index
+                    .tags
+                    .entry(tag.clone())
+                    .or_default()
+                    .insert(key.clone());
+            }
+        }
+        for (term, notes) in &index.stored.postings {
+            for key in notes.keys() {
+                index
+                    .terms
+                    .entry(key.clone())
+                    .or_default()
+                    .push(term.clone());
+            }
+        }
+        index
+    }
+
+    pub fn save(&mut self) -> Result<(), String> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        let content = serde_json::to_string(&self.stored).unwrap();
+        std::fs::write(&tmp, content)
+            .map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, &self.path)
+            .map_err(|e| format!("failed to replace {}: {}", self.path.display(), e))?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    fn key(&self, path: &Path) -> Option<String> {
+        let relative = path.strip_prefix(&self.notes_dir).ok()?;
+        Some(relative.to_string_lossy().replace('\\', "/"))
+    }
+
+    pub fn notes(&self) -> impl Iterator<Item = &IndexedNote> {
+        self.stored.notes.values()
+    }
+
+    /// The notes in `dir`, a folder relative to the notes dir like `2024/01`,
+    /// and its subfolders.
+    pub fn notes_in(&self, dir: &str) -> impl Iterator<Item = &IndexedNote> {
+        let prefix = format!("{}/", dir.trim_matches('/'));
+        self.stored
+            .notes
+            .range(prefix.clone()..)
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(_, note)| note)
+    }
+
+    fn is_current(&self, key: &str, modified: Option<DateTime<Local>>, size: u64) -> bool {
+        self.stored
+            .notes
+            .get(key)
+            .is_some_and(|note| note.modified == modified && note.size == size)
+    }
+
+    /// Forgets the note at `path`, or every note under it for a folder.
+    fn remove(&mut self, path: &Path) {
+        let Some(key) = self.key(path) else {
+            return;
+        };
+        let folder = format!("{}/", key);
+        let removed = self
+            .stored
+            .notes
+            .keys()
+            .filter(|note| **note == key || note.starts_with(&folder))
+            .cloned()
+            .collect::<Vec<String>>();
+        if removed.is_empty() {
+            return;
+        }
+        for key in &removed {
+            let Some(note) = self.stored.notes.remove(key) else {
+                continue;
+            };
+            for tag in note.tags {
+                if let Some(keys) = self.tags.get_mut(&tag) {
+                    keys.remove(key);
+                    if keys.is_empty() {
+                        self.tags.remove(&tag);
+                    }
+                }
+            }
+            for term in self.terms.remove(key).unwrap_or_default() {
+                if let Some(notes) = self.stored.postings.get_mut(&term) {
+                    notes.remove(key);
+                    if notes.is_empty() {
+                        self.stored.postings.remove(&term);
+                    }
+                }
+            }
+        }
+        self.dirty = true;
+    }
+
+    fn insert(&mut self, key: String, note: IndexedNote, tokens: Vec<Token>) {
+        self.remove(&note.path.clone());
+        let mut terms = Vec::new();
+        for (position, token) in tokens.into_iter().enumerate() {
+            let positions = self
+                .stored
+                .postings
+                .entry(token.text.clone())
+                .or_default()
+                .entry(key.clone())
+                .or_default();
+            if positions.is_empty() {
+                terms.push(token.text);
+            }
+            positions.push(position as u32);
+        }
+        self.terms.insert(key.clone(), terms);
+        for tag in &note.tags {
+            self.tags
+                .entry(tag.clone())
+                .or_default()
+                .insert(key.clone());
+        }
+        self.stored.notes.insert(key, note);
+        self.dirty = true;
+    }
+
+    /// Every tag and how many notes have it, by tag.
+    pub fn tags(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.tags
+            .iter()
+            .map(|(tag, keys)| (tag.as_str(), keys.len()))
+    }
+
+    /// The note a link points at. Of several notes about a video or with the
+    /// same title, the newest.
+    pub fn resolve(&self, target: &LinkTarget) -> Option<&IndexedNote> {
+        Resolver::new(self).resolve(target)
+    }
+
+    /// Every link between two notes, as the note it is in, the note it
+    /// points at and the link. Links of a note to itself, or to another note
+    /// about the same video, are left out.
+    pub fn links(&self) -> Vec<(&IndexedNote, &IndexedNote, &NoteLink)> {
+        let resolver = Resolver::new(self);
+        let mut found = Vec::new();
+        for from in self.notes() {
+            for link in &from.links {
+                if let Some(to) = resolver.resolve(&link.target) {
+                    let same = match (&from.video_id, &to.video_id) {
+                        (Some(from_video), Some(to_video)) => from_video == to_video,
+                        _ => to.path == from.path,
+                    };
+                    if !same {
+                        found.push((from, to, link));
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// The notes with `tag`, which must be normalized.
+    pub fn tagged(&self, tag: &str) -> impl Iterator<Item = &IndexedNote> {
+        self.tags
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.stored.notes.get(key))
+    }
+
+    /// For each note matching `clause`, the positions of the words matched.
+    fn evaluate(&self, clause: &Clause) -> HashMap<&str, Vec<u32>> {
+        let mut found: HashMap<&str, Vec<u32>> = HashMap::new();
+        match clause {
+            Clause::Word(word) => {
+                for (note, positions) in self.stored.postings.get(word).into_iter().flatten() {
+                    found.insert(note, positions.clone());
+                }
+            }
+            Clause::Prefix(prefix) => {
+                let words = self
+                    .stored
+                    .postings
+                    .range(prefix.clone()..)
+                    .take_while(|(word, _)| word.starts_with(prefix.as_str()));
+                for (_, notes) in words {
+                    for (note, positions) in notes {
+                        found.entry(note).or_default().extend(positions);
+                    }
+                }
+            }
+            Clause::Phrase(words) => {
+                let Some(first) = self.stored.postings.get(&words[0]) else {
+                    return found;
+                };
+                for (note, starts) in first {
+                    let mut matched = Vec::new();
+                    for start in starts {
+                        let rest_follows =
+                            words.iter().enumerate().skip(1).all(|(offset, word)| {
+                                self.stored
+                                    .postings
+                                    .get(word)
+                                    .and_then(|notes| notes.get(note))
+                                    .is_some_and(|positions| {
+                                        positions.binary_search(&(start + offset as u32)).is_ok()
+                                    })
+                            });
+                        if rest_follows {
+                            matched.extend((0..words.len() as u32).map(|offset| start + offset));
+                        }
+                    }
+                    if !matched.is_empty() {
+                        found.insert(note, matched);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Notes matching every clause of `query` and the filters, best first.
+    /// Snippets are left empty, see [`snippets`].
+    pub fn search(&self, query: &str, filters: &Filters, limit: usize) -> Vec<NoteHit> {
+        let clauses = parse_query(query);
+        if clauses.is_empty() {
+            return Vec::new();
+        }
+        let total = self.stored.notes.len() as f64;
+        let average_len = self
+            .stored
+            .notes
+            .values()
+            .map(|note| note.length as f64)
+            .sum::<f64>()
+            / total.max(1.0);
+
+        let mut scores: HashMap<&str, (f64, Vec<u32>)> = HashMap::new();
+        for (index, clause) in clauses.iter().enumerate() {
+            let found = self.evaluate(clause);
+            let df = found.len() as f64;
+            let idf = ranking::idf(total, df);
+            // Phrases count once per occurrence, not per word
+            let words = match clause {
+                Clause::Phrase(words) => words.len() as f64,
+                _ => 1.0,
+            };
+            let mut next = HashMap::new();
+            for (note, positions) in found {
+                // Every clause must match
+                let previous = match scores.remove(note) {
+                    Some(previous) => previous,
+                    None if index == 0 => (0.0, Vec::new()),
+                    None => continue,
+                };
+                let length = self.stored.notes[note].length as f64;
+                let tf = positions.len() as f64 / words;
+                let score = ranking::bm25(idf, tf, length, average_len) * words;
+                let mut all_positions = previous.1;
+                all_positions.extend(positions);
+                next.insert(note, (previous.0 + score, all_positions));
+            }
+            scores = next;
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .map(|(key, (score, mut positions))| {
+                positions.sort();
+                positions.dedup();
+                (&self.stored.notes[key], score, positions)
+            })
+            .filter(|(note, _, _)| filters.matches(note))
+            .map(|(note, score, positions)| NoteHit {
+                note: note.clone(),
+                score,
+                snippets: Vec::new(),
+                positions,
+            })
+            .collect::<Vec<NoteHit>>();
+        hits.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then(b.note.date.cmp(&a.note.date))
+        });
+        hits.truncate(limit);
+        hits
+    }
+}
+
+/// Up to [`MAX_SNIPPETS`] bits of `content` around the words at
+/// `positions`, with those words marked.
+pub fn snippets(content: &str, positions: &[u32]) -> Vec<String> {
+    let tokens = ranking::tokens(content);
+    let escape = |text: &str| {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace(['\r', '\n'], " ")
+    };
+    let mut snippets = Vec::new();
+    let mut covered_until = 0;
+    for &position in positions {
+        let position = position as usize;
+        if position >= tokens.len() || (position < covered_until && !snippets.is_empty()) {
+            continue;
+        }
+        if snippets.len() == MAX_SNIPPETS {
+            break;
+        }
+        let first = position.saturating_sub(SNIPPET_WORDS);
+        let last = (position + SNIPPET_WORDS * 2).min(tokens.len() - 1);
+        covered_until = last + 1;
+        let mut snippet = String::new();
+        if first > 0 {
+            snippet.push('…');
+        }
+        let mut at = tokens[first].start;
+        for (index, token) in tokens.iter().enumerate().take(last + 1).skip(first) {
+            snippet.push_str(&escape(&content[at..token.start]));
+            let word = escape(&content[token.start..token.end]);
+            if positions.binary_search(&(index as u32)).is_ok() {
+                snippet.push_str(&format!("<mark>{}</mark>", word));
+            } else {
+                snippet.push_str(&word);
+            }
+            at = token.end;
+        }
+        if last + 1 < tokens.len() {
+            snippet.push('…');
+        }
+        snippets.push(snippet.split_whitespace().collect::<Vec<&str>>().join(" "));
+    }
+    snippets
+}
+
+/// The date of a note, from its id or its dated folder (`YYYY/MM/DD`).
+fn note_date(id: &str, key: &str) -> Option<NaiveDate> {
+    let from_id = id
+        .strip_prefix('[')
+        .and_then(|rest| rest.get(..10))
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+    from_id.or_else(|| {
+        let parts = key.split('/').collect::<Vec<&str>>();
+        parts
+            .windows(3)
+            .find_map(|parts| NaiveDate::parse_from_str(&parts.join("-"), "%Y-%m-%d").ok())
+    })
+}
+
+/// Indexes the note at `path` if it changed since it was last indexed.
+async fn index_file(index: &Mutex<NotesIndex>, metadata: &MetadataCache, path: PathBuf) {
+    let Ok(file_metadata) = tokio::fs::metadata(&path).await else {
+        index.lock().await.remove(&path);
+        return;
+    };
+    let created = file_metadata.created().ok().map(DateTime::<Local>::from);
+    let modified = file_metadata.modified().ok().map(DateTime::<Local>::from);
+    let size = file_metadata.len();
+    let key = {
+        let index = index.lock().await;
+        match index.key(&path) {
+            Some(key) if !index.is_current(&key, modified, size) => key,
+            _ => return,
+        }
+    };
+    let content = match tokio::fs::read_to_string(&path).await {
+        Ok(it) => it,
+        Err(err) => {
+            warn!("Not indexing {}: {}", path.display(), err);
+            return;
+        }
+    };
+    let id = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let video_id = notes::video_id_from_note_id(&id).map(str::to_string);
+    let video = match &video_id {
+        Some(video_id) => metadata.get(video_id).await,
+        None => None,
+    };
+    let channel = video
+        .as_ref()
+        .and_then(|video| video.channel_name().map(str::to_string));
+    let generated = video
+        .as_ref()
+        .map(note_template::generated_lines)
+        .unwrap_or_default();
+    // Links to the note's own video and the ones a template filled in from
+    // the video's metadata aren't links the user made
+    let links = links::extract(&content, &generated)
+        .into_iter()
+        .filter(|link| match &link.target {
+            LinkTarget::Video(target) => video_id.as_ref() != Some(target),
+            LinkTarget::Title(_) => true,
+        })
+        .collect();
+    let tokens = ranking::tokens(&content);
+    let note = IndexedNote {
+        date: note_date(&id, &key),
+        id,
+        path,
+        created,
+        modified,
+        size,
+        video_id,
+        channel,
+        tags: tags::extract(&content),
+        links,
+        length: tokens.len() as u32,
+    };
+    index.lock().await.insert(key, note, tokens);
+}
+
+/// Brings the index up to date with `path`: a note, a folder of notes, or
+/// something that no longer exists.
+async fn update_path(index: &Mutex<NotesIndex>, metadata: &MetadataCache, path: PathBuf) {
+    if path.is_dir() {
+        let files = tokio::task::spawn_blocking(move || notes::note_files(&path))
+            .await
+            .unwrap_or_default();
+        for file in files {
+            index_file(index, metadata, file).await;
+        }
+    } else if path.exists() {
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            index_file(index, metadata, path).await;
+        }
+    } else {
+        index.lock().await.remove(&path);
+    }
+}
+
+/// Starts keeping `index` up to date: first with whatever changed while the
+/// server wasn't running, then with every change under the notes dir. Paths
+/// sent to the returned sender are indexed too, which `/set_note` uses so
+/// its saves don't depend on the watcher.
+pub fn start(
+    index: Arc<Mutex<NotesIndex>>,
+    metadata: Arc<MetadataCache>,
+) -> mpsc::UnboundedSender<PathBuf> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    tokio::spawn(run(index, metadata, tx.clone(), rx));
+    tx
+}
+
+async fn run(
+    index: Arc<Mutex<NotesIndex>>,
+    metadata: Arc<MetadataCache>,
+    tx: mpsc::UnboundedSender<PathBuf>,
+    mut rx: mpsc::UnboundedReceiver<PathBuf>,
+) {
+    let notes_dir = index.lock().await.notes_dir.clone();
+    let dir = notes_dir.clone();
+    let files = tokio::task::spawn_blocking(move || notes::note_files(&dir))
+        .await
+        .unwrap_or_default();
+    let gone = {
+        let index = index.lock().await;
+        index
+            .notes()
+            .map(|note| note.path.clone())
+            .filter(|path| !files.contains(path))
+            .collect::<Vec<PathBuf>>()
+    };
+    for path in gone {
+        index.lock().await.remove(&path);
+    }
+    for file in files {
+        index_file(&index, &metadata, file).await;
+    }
+    save(&index).await;
+    info!(
+        "Notes index is up to date with {} notes",
+        index.lock().await.notes().count()
+    );
+
+    // Kept alive for as long as this task runs
+    let watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => event.paths.into_iter().for_each(|path| {
+                let _ = tx.send(path);
+            }),
+            Err(err) => warn!("Notes watcher error: {}", err),
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&notes_dir, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        });
+    let _watcher = match watcher {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            error!(
+                "Not watching {} for changes, notes are only indexed when saved through the server: {}",
+                notes_dir.display(),
+                err
+            );
+            None
+        }
+    };
+
+    let mut saved = Instant::now();
+    loop {
+        let path = match tokio::time::timeout(SAVE_INTERVAL, rx.recv()).await {
+            Ok(Some(path)) => path,
+            Ok(None) => break,
+            Err(_) => {
+                save(&index).await;
+                saved = Instant::now();
+                continue;
+            }
+        };
+        let mut changed = BTreeSet::from([path]);
+        let deadline = Instant::now() + MAX_DELAY;
+        while let Ok(Some(path)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+            changed.insert(path);
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        for path in changed {
+            update_path(&index, &metadata, path).await;
+        }
+        if saved.elapsed() >= SAVE_INTERVAL {
+            save(&index).await;
+            saved = Instant::now();
+        }
+    }
+    save(&index).await;
+}
+
+async fn save(index: &Mutex<NotesIndex>) {
+    if let Err(err) = index.lock().await.save() {
+        error!("Error saving notes index: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(index: &NotesIndex, name: &str) -> (String, IndexedNote) {
+        let path = index.notes_dir.join(format!("{}.txt", name));
+        let note = IndexedNote {
+            id: name.to_string(),
+            path: path.clone(),
+            created: None,
+            modified: None,
+            size: 0,
+            date: None,
+            video_id: None,
+            channel: None,
+            tags: Vec::new(),
+            length: 0,
+            links: Vec::new(),
+        };
+        (index.key(&path).unwrap(), note)
+    }
+
+    fn video_note(
+        index: &NotesIndex,
+        name: &str,
+        video_id: &str,
+        content: &str,
+    ) -> (String, IndexedNote) {
+        let (key, mut note) = note(index, name);
+        note.video_id = Some(video_id.to_string());
+        note.links = links::extract(content, &[]);
+        (key, note)
+    }
+
+    #[test]
+    fn links_between_notes_of_one_video_are_left_out() {
+        let mut index = NotesIndex::load(
+            PathBuf::from("/nonexistent/notes_index.json"),
+            PathBuf::from("/notes"),
+        );
+        let other = "https://www.youtube.com/watch?v=bbbbbbbbbbb";
+        let notes = [
+            video_note(
+                &index,
+                "first watch",
+                "aaaaaaaaaaa",
+                "https://youtu.be/aaaaaaaaaaa",
+            ),
+            video_note(&index, "second watch", "aaaaaaaaaaa", other),
+            video_note(&index, "other", "bbbbbbbbbbb", "[[first watch]]"),
+        ];
+        for (key, note) in notes {
+            index.insert(key, note, Vec::new());
+        }
+        let found = index
+            .links()
+            .into_iter()
+            .map(|(from, to, _)| (from.id.as_str(), to.id.as_str()))
+            .collect::<BTreeSet<_>>();
+        assert_eq!(
+            found,
+            BTreeSet::from([("other", "first watch"), ("second watch", "other")])
+        );
+    }
+
+    #[test]
+    fn removing_a_note_keeps_the_others() {
+        let mut index = NotesIndex::load(
+            PathBuf::from("/nonexistent/notes_index.json"),
+            PathBuf::from("/notes"),
+        );
+        let (key, first) = note(&index, "first");
+        index.insert(key, first, ranking::tokens("shared words here"));
+        let (key, second) = note(&index, "second");
+        index.insert(
+            key.clone(),
+            second.clone(),
+            ranking::tokens("shared other words"),
+        );
+        let search = |index: &NotesIndex, query: &str| {
+            index
+                .search(query, &Filters::default(), 10)
+                .into_iter()
+                .map(|hit| hit.note.id)
+                .collect::<BTreeSet<String>>()
+        };
+        assert_eq!(search(&index, "shared").len(), 2);
+
+        // Indexing a note again replaces its words
+        index.insert(key, second.clone(), ranking::tokens("only new"));
+        assert_eq!(
+            search(&index, "shared"),
+            BTreeSet::from(["first".to_string()])
+        );
+        assert_eq!(
+            search(&index, "new"),
+            BTreeSet::from(["second".to_string()])
+        );
+
+        index.remove(&second.path);
+        assert!(search(&index, "new").is_empty());
+        assert!(!index.stored.postings.contains_key("only"));
+        assert_eq!(
+            search(&index, "here"),
+            BTreeSet::from(["first".to_string()])
+        );
+    }
+
+    fn words(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn queries_have_words_prefixes_and_phrases() {
+        assert_eq!(
+            parse_query(r#"Rust borrow* "Borrow Checker" "single" x* "" -"#),
+            vec![
+                Clause::Word("rust".to_string()),
+                Clause::Prefix("borrow".to_string()),
+                Clause::Phrase(words(&["borrow", "checker"])),
+                Clause::Word("single".to_string()),
+                Clause::Prefix("x".to_string()),
+            ]
+        );
+        // A star inside quotes is punctuation, and an unclosed quote runs to the end
+        assert_eq!(
+            parse_query(r#"tokio "async* runtime"#),
+            vec![
+                Clause::Word("tokio".to_string()),
+                Clause::Phrase(words(&["async", "runtime"])),
+            ]
+        );
+        assert_eq!(parse_query("  -- "), vec![]);
+    }
+
+    fn indexed(notes: &[(&str, &str)]) -> NotesIndex {
+        let mut index = NotesIndex::load(
+            PathBuf::from("/nonexistent/notes_index.json"),
+            PathBuf::from("/notes"),
+        );
+        for (name, content) in notes {
+            let (key, mut note) = note(&index, name);
+            let tokens = ranking::tokens(content);
+            note.length = tokens.len() as u32;
+            index.insert(key, note, tokens);
+        }
+        index
+    }
+
+    /// Note id to the positions `clause` matched in it.
+    fn evaluated(index: &NotesIndex, clause: Clause) -> BTreeMap<String, Vec<u32>> {
+        index
+            .evaluate(&clause)
+            .into_iter()
+            .map(|(key, mut positions)| {
+                positions.sort();
+                (key.trim_end_matches(".txt").to_string(), positions)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn phrases_match_words_in_order() {
+        let index = indexed(&[
+            ("a", "the borrow checker checks, borrow checker again"),
+            ("b", "checker borrow"),
+            ("c", "borrow the checker"),
+        ]);
+        assert_eq!(
+            evaluated(&index, Clause::Phrase(words(&["borrow", "checker"]))),
+            BTreeMap::from([("a".to_string(), vec![1, 2, 4, 5])])
+        );
+        assert_eq!(
+            evaluated(&index, Clause::Prefix("check".to_string())),
+            BTreeMap::from([
+                ("a".to_string(), vec![2, 3, 5]),
+                ("b".to_string(), vec![0]),
+                ("c".to_string(), vec![2]),
+            ])
+        );
+        assert!(evaluated(&index, Clause::Phrase(words(&["nothing", "here"]))).is_empty());
+
+        let hits = index.search(r#""borrow checker""#, &Filters::default(), 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].positions, vec![1, 2, 4, 5]);
+        // Every clause must match
+        let hits = index.search("borrow the", &Filters::default(), 10);
+        let ids = hits
+            .iter()
+            .map(|hit| hit.note.id.as_str())
+            .collect::<BTreeSet<_>>();
+        assert_eq!(ids, BTreeSet::from(["a", "c"]));
+    }
+
+    #[test]
+    fn snippets_mark_matches_and_escape_the_rest() {
+        let content = "Use <b>Rust</b> & the\nborrow checker";
+        assert_eq!(
+            snippets(content, &[2, 5]),
+            vec!["Use &lt;b&gt;<mark>Rust</mark>&lt;/b&gt; &amp; the <mark>borrow</mark> checker"]
+        );
+
+        let long = (0..60)
+            .map(|n| format!("w{}", n))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let found = snippets(&long, &[10, 12, 50, 55]);
+        assert_eq!(found.len(), MAX_SNIPPETS);
+        assert!(found[0].starts_with("…w2 "));
+        assert!(found[0].contains("<mark>w10</mark> w11 <mark>w12</mark>"));
+        assert!(found[0].ends_with(" w26…"));
+        // Matches covered by the first snippet don't start another
+        assert!(found[1].starts_with("…w42 "));
+        assert!(found[1].ends_with("<mark>w55</mark> w56 w57 w58 w59"));
+        assert!(snippets("short", &[3]).is_empty());
+    }
+
+    #[test]
+    fn filters_bound_dates_channels_and_tags() {
+        let index = NotesIndex::load(
+            PathBuf::from("/nonexistent/notes_index.json"),
+            PathBuf::from("/notes"),
+        );
+        let (_, mut dated) = note(&index, "dated");
+        dated.date = NaiveDate::from_ymd_opt(2024, 3, 15);
+        dated.channel = Some("Some Channel".to_string());
+        dated.tags = vec!["rust".to_string()];
+        let day = |day| NaiveDate::from_ymd_opt(2024, 3, day);
+
+        assert!(Filters::default().matches(&dated));
+        let inclusive = Filters {
+            from: day(15),
+            to: day(15),
+            ..Filters::default()
+        };
+        assert!(inclusive.matches(&dated));
+        assert!(!Filters {
+            from: day(16),
+            ..Filters::default()
+        }
+        .matches(&dated));
+        assert!(!Filters {
+            to: day(14),
+            ..Filters::default()
+        }
+        .matches(&dated));
+        let channel_and_tag = Filters {
+            channel: Some("some channel".to_string()),
+            tag: Some("#Rust".to_string()),
+            ..Filters::default()
+        };
+        assert!(channel_and_tag.matches(&dated));
+        assert!(!Filters {
+            channel: Some("Other".to_string()),
+            ..Filters::default()
+        }
+        .matches(&dated));
+        assert!(!Filters {
+            tag: Some("go".to_string()),
+            ..Filters::default()
+        }
+        .matches(&dated));
+
+        // Notes without a date or channel don't pass bounds on them
+        let (_, undated) = note(&index, "undated");
+        assert!(!inclusive.matches(&undated));
+        assert!(!channel_and_tag.matches(&undated));
+    }
+}
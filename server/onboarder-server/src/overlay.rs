@@ -0,0 +1,278 @@
+use crate::timestamps::text_after;
+use crate::timestamps::timed_lines;
+use crate::timestamps::LineKind;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// How long a cue stays up when the request doesn't say.
+const DEFAULT_CUE_SECONDS: f64 = 5.0;
+
+/// The latest time ASS can write, as it has a single digit of hours.
+const ASS_MAX_SECONDS: f64 = 9.0 * 3600.0 + 59.0 * 60.0 + 59.99;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverlayFormat {
+    #[default]
+    Srt,
+    /// Notes in the top left and events in the top right, clear of the
+    /// video's own subtitles.
+    Ass,
+}
+
+impl OverlayFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OverlayFormat::Srt => "notes.srt",
+            OverlayFormat::Ass => "notes.ass",
+        }
+    }
+}
+
+/// Body of `POST /note_subtitles`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct OverlayRequest {
+    pub video_id: String,
+    #[serde(default)]
+    pub format: OverlayFormat,
+    /// How long each cue stays up, in seconds.
+    #[serde(default)]
+    pub cue_seconds: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cue {
+    /// In seconds.
+    pub start: f64,
+    /// In seconds.
+    pub end: f64,
+    pub text: String,
+    pub event: bool,
+}
+
+/// One cue per line of `content` that mentions a position, showing the
+/// line. Timestamp lines from the extension show the note written after
+/// them instead, and its event lines show just the event.
+pub fn cues_from_note(content: &str, cue_seconds: f64, duration: Option<f64>) -> Vec<Cue> {
+    let lines = content.lines().collect::<Vec<&str>>();
+    let mut cues = timed_lines(content)
+        .into_iter()
+        .filter_map(|timed| {
+            let (text, event) = match timed.kind {
+                LineKind::Timestamp => (text_after(&lines, timed.index).join("\n"), false),
+                LineKind::Event(event) => (event, true),
+                LineKind::Text => (lines[timed.index].trim().to_string(), false),
+            };
+            if text.is_empty() {
+                return None;
+            }
+            let mut end = timed.position + cue_seconds;
+            if let Some(duration) = duration.filter(|duration| *duration > timed.position) {
+                end = end.min(duration);
+            }
+            Some(Cue {
+                start: timed.position,
+                end,
+                text,
+                event,
+            })
+        })
+        .collect::<Vec<Cue>>();
+    // Notes aren't always written in video order, players want cues that are
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    cues
+}
+
+/// `H:MM:SS` followed by `separator` and `digits` digits of fractions.
+fn cue_time(seconds: f64, separator: char, digits: usize) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let fraction = millis % 1000 / 10u64.pow(3 - digits as u32);
+    format!(
+        "{:02}:{:02}:{:02}{}{:0width$}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        separator,
+        fraction,
+        width = digits
+    )
+}
+
+/// `H:MM:SS.cc` as ASS wants it, clamped to [`ASS_MAX_SECONDS`].
+fn ass_time(seconds: f64) -> String {
+    cue_time(seconds.min(ASS_MAX_SECONDS), '.', 2)[1..].to_string()
+}
+
+pub fn to_srt(cues: &[Cue]) -> String {
+    let mut srt = String::new();
+    for (index, cue) in cues.iter().enumerate() {
+        let text = if cue.event {
+            format!("<i>{}</i>", cue.text)
+        } else {
+            cue.text.clone()
+        };
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            index + 1,
+            cue_time(cue.start, ',', 3),
+            cue_time(cue.end, ',', 3),
+            text
+        ));
+    }
+    srt
+}
+
+pub fn to_ass(cues: &[Cue], title: &str) -> String {
+    let escape = |text: &str| {
+        text.replace('\\', "\\\\")
+            .replace('{', "\\{")
+            .replace('}', "\\}")
+            .replace('\n', "\\N")
+    };
+    let mut ass = format!(
+        "[Script Info]\n\
+         Title: {}\n\
+         ScriptType: v4.00+\n\
+         WrapStyle: 0\n\
+         PlayResX: 1920\n\
+         PlayResY: 1080\n\
+         \n\
+         [V4+ Styles]\n\
+         Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Note,Arial,44,&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,3,2,0,7,40,40,40,1\n\
+         Style: Event,Arial,32,&H00C0C0C0,&H000000FF,&H00000000,&H80000000,0,1,0,0,100,100,0,0,3,2,0,9,40,40,40,1\n\
+         \n\
+         [Events]\n\
+         Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        title.replace('\n', " ")
+    );
+    for cue in cues {
+        ass.push_str(&format!(
+            "Dialogue: 0,{},{},{},,0,0,0,,{}\n",
+            ass_time(cue.start),
+            ass_time(cue.end),
+            if cue.event { "Event" } else { "Note" },
+            escape(&cue.text)
+        ));
+    }
+    ass
+}
+
+/// Where the overlay for `media` goes: next to it, named after it, so
+/// players that look for subtitles by name find it.
+pub fn output_path(media: &Path, format: OverlayFormat) -> PathBuf {
+    let stem = media
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    media.with_file_name(format!("{}.{}", stem, format.extension()))
+}
+
+/// Writes the cues of `note` next to `media`, returning the file and how
+/// many cues it has, or `None` if the note has no timed lines.
+pub fn write(
+    note: &Path,
+    media: &Path,
+    title: &str,
+    request: &OverlayRequest,
+    duration: Option<f64>,
+) -> Result<Option<(PathBuf, usize)>, String> {
+    let content = std::fs::read_to_string(note)
+        .map_err(|e| format!("Failed to read {}: {}", note.display(), e))?;
+    let cue_seconds = request
+        .cue_seconds
+        .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+        .unwrap_or(DEFAULT_CUE_SECONDS);
+    let cues = cues_from_note(&content, cue_seconds, duration);
+    if cues.is_empty() {
+        return Ok(None);
+    }
+    let text = match request.format {
+        OverlayFormat::Srt => to_srt(&cues),
+        OverlayFormat::Ass => to_ass(&cues, title),
+    };
+    let path = output_path(media, request.format);
+    std::fs::write(&path, text)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(Some((path, cues.len())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "\
+- 3:00 the second point
+video current time 00:01:05 / 00:10:00 (10.83%) at Sat Oct 19 2026 05:41:02 GMT-0400 (Eastern Daylight Time)
+first point
+[✂ 1:05–2:10](</videos/Talk (aaaaaaaaaaa) 1m05.000s-2m10.000s.mp4>)
+and {more} of it
+Sat Oct 19 2026 05:44:01 GMT-0400 (Eastern Daylight Time) --- 00:09:58 / 00:10:00 (99.67%) --- paused
+";
+
+    #[test]
+    fn cue_times() {
+        assert_eq!(cue_time(0.0, ',', 3), "00:00:00,000");
+        assert_eq!(cue_time(3723.4567, ',', 3), "01:02:03,457");
+        assert_eq!(cue_time(3723.4567, '.', 2), "01:02:03.45");
+        assert_eq!(cue_time(-5.0, ',', 3), "00:00:00,000");
+        assert_eq!(ass_time(3723.45), "1:02:03.45");
+        assert_eq!(ass_time(36000.0), "9:59:59.99");
+        assert_eq!(ass_time(100_000.0), "9:59:59.99");
+    }
+
+    #[test]
+    fn cues_in_video_order() {
+        let cues = cues_from_note(NOTE, 5.0, Some(600.0));
+        assert_eq!(
+            cues,
+            vec![
+                Cue {
+                    start: 65.0,
+                    end: 70.0,
+                    text: "first point\nand {more} of it".to_string(),
+                    event: false,
+                },
+                Cue {
+                    start: 180.0,
+                    end: 185.0,
+                    text: "- 3:00 the second point".to_string(),
+                    event: false,
+                },
+                Cue {
+                    start: 598.0,
+                    end: 600.0,
+                    text: "paused".to_string(),
+                    event: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn srt_and_ass_output() {
+        let cues = cues_from_note(NOTE, 5.0, Some(600.0));
+        assert_eq!(
+            to_srt(&cues),
+            "1\n00:01:05,000 --> 00:01:10,000\nfirst point\nand {more} of it\n\n\
+             2\n00:03:00,000 --> 00:03:05,000\n- 3:00 the second point\n\n\
+             3\n00:09:58,000 --> 00:10:00,000\n<i>paused</i>\n\n"
+        );
+        let ass = to_ass(&cues, "A\ntitle");
+        assert!(ass.contains("Title: A title\n"));
+        let dialogue = ass
+            .lines()
+            .filter(|line| line.starts_with("Dialogue:"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            dialogue,
+            vec![
+                "Dialogue: 0,0:01:05.00,0:01:10.00,Note,,0,0,0,,first point\\Nand \\{more\\} of it",
+                "Dialogue: 0,0:03:00.00,0:03:05.00,Note,,0,0,0,,- 3:00 the second point",
+                "Dialogue: 0,0:09:58.00,0:10:00.00,Event,,0,0,0,,paused",
+            ]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::jobs;
+use crate::jobs::JobLog;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// One command run after a profile's download finishes, e.g. an ffmpeg
+/// remux or thumbnail extraction.
+///
+/// In `command`, `{file}` is replaced with the downloaded file, `{dir}` with
+/// its directory, `{stem}` with its path minus the extension, `{video_id}`
+/// with the video id and `{note}` with the video's note file (empty if it
+/// has none yet).
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PostStep {
+    /// Shown in the job log, defaults to the program name.
+    pub name: Option<String>,
+    /// Program and arguments, not run through a shell.
+    pub command: Vec<String>,
+}
+
+impl PostStep {
+    fn name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.command.first().map(String::as_str))
+            .unwrap_or("unnamed step")
+    }
+}
+
+/// Runs `steps` in order for each of `files`, stopping at the first failure.
+/// Each step runs in the file's directory with its output going to `log`.
+pub async fn run(
+    steps: &[PostStep],
+    files: &[PathBuf],
+    video_id: &str,
+    note: Option<&Path>,
+    log: &JobLog,
+) -> Result<(), String> {
+    let note = note
+        .map(|note| note.display().to_string())
+        .unwrap_or_default();
+    for file in files {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        let values = [
+            ("{file}", file.display().to_string()),
+            ("{dir}", dir.display().to_string()),
+            ("{stem}", file.with_extension("").display().to_string()),
+            ("{video_id}", video_id.to_string()),
+            ("{note}", note.clone()),
+        ];
+        for (index, step) in steps.iter().enumerate() {
+            let args = step
+                .command
+                .iter()
+                .map(|arg| {
+                    values
+                        .iter()
+                        .fold(arg.clone(), |arg, (placeholder, value)| {
+                            arg.replace(placeholder, value)
+                        })
+                })
+                .collect::<Vec<String>>();
+            let Some((program, args)) = args.split_first() else {
+                return Err(format!(
+                    "Post-processing step {:?} has no command",
+                    step.name()
+                ));
+            };
+            log.line(&format!(
+                "[post-process] Step {}/{} {} on {}",
+                index + 1,
+                steps.len(),
+                step.name(),
+                file.display()
+            ))
+            .await?;
+            let mut command = tokio::process::Command::new(program);
+            command.current_dir(dir).args(args);
+            jobs::run_command(log, command)
+                .await
+                .map_err(|e| format!("Post-processing step {:?} failed: {}", step.name(), e))?;
+        }
+    }
+    Ok(())
+}
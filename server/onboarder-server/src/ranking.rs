@@ -0,0 +1,82 @@
+/// BM25 parameters, the usual defaults.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// A word of a text and where it is, in bytes.
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    /// Lowercased.
+    pub text: String,
+}
+
+/// The runs of letters and digits in `text`, which is what both the note and
+/// the transcript indexes search by.
+pub fn tokens(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(index),
+            (false, Some(from)) => {
+                tokens.push(Token {
+                    start: from,
+                    end: index,
+                    text: text[from..index].to_lowercase(),
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// The lowercased words of `text`, see [`tokens`].
+pub fn words(text: &str) -> Vec<String> {
+    tokens(text).into_iter().map(|token| token.text).collect()
+}
+
+/// How telling a term found in `found` of `total` documents is.
+pub fn idf(total: f64, found: f64) -> f64 {
+    ((total - found + 0.5) / (found + 0.5) + 1.0).ln()
+}
+
+/// The BM25 score of a term with weight `idf` occurring `count` times in a
+/// document of `len` words, where documents average `average_len` words.
+pub fn bm25(idf: f64, count: f64, len: f64, average_len: f64) -> f64 {
+    let norm = K1 * (1.0 - B + B * len / average_len.max(1.0));
+    idf * count * (K1 + 1.0) / (count + norm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_lowercased_words_with_offsets() {
+        let text = "Ünïcode, C++ & RUST2024!";
+        let tokens = tokens(text);
+        let found = tokens
+            .iter()
+            .map(|token| (token.text.as_str(), &text[token.start..token.end]))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![("ünïcode", "Ünïcode"), ("c", "C"), ("rust2024", "RUST2024")]
+        );
+        assert!(words(" -- ").is_empty());
+    }
+
+    #[test]
+    fn rarer_and_more_frequent_terms_score_higher() {
+        assert!(idf(100.0, 1.0) > idf(100.0, 50.0));
+        assert!(idf(100.0, 100.0) > 0.0);
+        let idf = idf(100.0, 10.0);
+        assert!(bm25(idf, 3.0, 20.0, 20.0) > bm25(idf, 1.0, 20.0, 20.0));
+        // Longer documents need more occurrences for the same score
+        assert!(bm25(idf, 1.0, 10.0, 20.0) > bm25(idf, 1.0, 40.0, 20.0));
+        // Saturates rather than growing without bound
+        assert!(bm25(idf, 1000.0, 20.0, 20.0) < idf * 2.2 + 1e-9);
+    }
+}
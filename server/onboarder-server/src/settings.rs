@@ -0,0 +1,170 @@
+use crate::chapters::ChapterSettings;
+use crate::downloader::DownloaderSettings;
+use crate::downloader::MediaKind;
+use crate::ffmpeg::FfmpegSettings;
+use crate::file_search::SearchSettings;
+use crate::note_template::NoteTemplates;
+use crate::postprocess::PostStep;
+use crate::subscriptions::SubscriptionSettings;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+use tracing::info;
+
+/// Contents of the settings file passed with `--settings`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    pub profiles: BTreeMap<String, Profile>,
+    /// Command used to show a job's live log when a console is requested.
+    /// `{log}` is replaced with the path of the job log.
+    pub console_command: Vec<String>,
+    pub downloader: DownloaderSettings,
+    pub search: SearchSettings,
+    pub note_templates: NoteTemplates,
+    pub subscriptions: SubscriptionSettings,
+    pub ffmpeg: FfmpegSettings,
+    pub chapters: ChapterSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let common = || {
+            vec![
+                "--windows-filenames".to_string(),
+                "--embed-metadata".to_string(),
+            ]
+        };
+        let mut profiles = BTreeMap::new();
+        profiles.insert(
+            "video".to_string(),
+            Profile {
+                subtitle_langs: vec!["en".to_string()],
+                extra_args: common(),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "audio".to_string(),
+            Profile {
+                kind: MediaKind::Audio,
+                format: Some("bestaudio".to_string()),
+                extra_args: [vec!["--extract-audio".to_string()], common()].concat(),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "subtitles".to_string(),
+            Profile {
+                kind: MediaKind::Subtitles,
+                subtitle_langs: vec!["en".to_string()],
+                extra_args: vec!["--skip-download".to_string()],
+                ..Default::default()
+            },
+        );
+        let console_command = if cfg!(windows) {
+            vec![
+                "wt",
+                "pwsh.exe",
+                "-NoProfile",
+                "-Command",
+                "Get-Content -Wait -LiteralPath '{log}'",
+            ]
+        } else {
+            vec!["x-terminal-emulator", "-e", "tail", "-f", "{log}"]
+        };
+        Settings {
+            profiles,
+            console_command: console_command.into_iter().map(String::from).collect(),
+            downloader: DownloaderSettings::default(),
+            search: SearchSettings::default(),
+            note_templates: NoteTemplates::default(),
+            subscriptions: SubscriptionSettings::default(),
+            ffmpeg: FfmpegSettings::default(),
+            chapters: ChapterSettings::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// A missing file means "use the defaults"; a malformed one is an error.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            info!(
+                "No settings file at {}, using default settings",
+                path.display()
+            );
+            return Ok(Settings::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        let settings: Settings = serde_json::from_str(&content)
+            .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?;
+        info!(
+            "Loaded settings from {} with profiles {:?}",
+            path.display(),
+            settings.profiles.keys().collect::<Vec<_>>()
+        );
+        Ok(settings)
+    }
+
+    pub fn profile(&self, name: &str) -> Result<&Profile, String> {
+        self.profiles.get(name).ok_or_else(|| {
+            format!(
+                "Unknown profile \"{}\", expected one of {:?}",
+                name,
+                self.profiles.keys().collect::<Vec<_>>()
+            )
+        })
+    }
+}
+
+/// A named set of yt-dlp options.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Profile {
+    pub kind: MediaKind,
+    /// Passed as `-f`.
+    pub format: Option<String>,
+    /// Passed as `-o`.
+    pub output_template: Option<String>,
+    /// When non-empty, subtitles (including auto-generated ones) are written for these languages.
+    pub subtitle_langs: Vec<String>,
+    /// Passed as `--cookies`.
+    pub cookies_file: Option<PathBuf>,
+    pub extra_args: Vec<String>,
+    /// Open a console showing the job log whenever this profile is used.
+    pub show_console: bool,
+    /// Run in order on each downloaded file of the profile's kind once the
+    /// download finishes. A failing step fails the job.
+    pub post_process: Vec<PostStep>,
+}
+
+impl Profile {
+    /// The yt-dlp arguments for this profile, excluding the URL.
+    pub fn ytdlp_args(&self) -> Vec<String> {
+        let mut args = vec!["--encoding".to_string(), "utf-8".to_string()];
+        if let Some(format) = &self.format {
+            args.push("-f".to_string());
+            args.push(format.clone());
+        }
+        if let Some(output_template) = &self.output_template {
+            args.push("-o".to_string());
+            args.push(output_template.clone());
+        }
+        if !self.subtitle_langs.is_empty() {
+            args.push("--write-subs".to_string());
+            args.push("--write-auto-subs".to_string());
+            args.push("--sub-langs".to_string());
+            args.push(self.subtitle_langs.join(","));
+        }
+        if let Some(cookies_file) = &self.cookies_file {
+            args.push("--cookies".to_string());
+            args.push(cookies_file.display().to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
@@ -0,0 +1,191 @@
+use crate::ffmpeg;
+use crate::ffmpeg::FfmpegSettings;
+use crate::jobs::JobId;
+use crate::jobs::JobLog;
+use crate::jobs::Jobs;
+use crate::jobs::NoteLine;
+use crate::timestamps::file_label;
+use crate::timestamps::find_timestamps;
+use crate::timestamps::format_timestamp;
+use crate::timestamps::is_media_line;
+use crate::timestamps::timed_lines;
+use crate::timestamps::LineKind;
+use std::path::Path;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+
+/// The folder next to `note` that its images go in, named after it.
+pub fn attachments_dir(note: &Path) -> PathBuf {
+    note.with_extension("attachments")
+}
+
+/// The Markdown image showing the frame at `at` in `note`, with a path
+/// relative to the note so the notes dir can be moved.
+fn image_line(note: &Path, at: f64) -> String {
+    let dir = attachments_dir(note);
+    let dir_name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    format!(
+        "![{}](<{}/{}.jpg>)",
+        format_timestamp(at),
+        dir_name,
+        file_label(at)
+    )
+}
+
+/// Every position written in `content` that doesn't have its image in the
+/// note yet, in order of appearance. The play and pause events the
+/// extension logs are left out.
+pub fn missing_positions(note: &Path, content: &str) -> Vec<f64> {
+    let events = timed_lines(content)
+        .into_iter()
+        .filter(|timed| matches!(timed.kind, LineKind::Event(_)))
+        .map(|timed| timed.index)
+        .collect::<Vec<usize>>();
+    let mut labels = Vec::new();
+    let mut positions = Vec::new();
+    for (_, line) in content
+        .lines()
+        .enumerate()
+        .filter(|(index, line)| !is_media_line(line) && !events.contains(index))
+    {
+        for at in find_timestamps(line) {
+            let label = file_label(at);
+            if !labels.contains(&label) && !content.contains(&image_line(note, at)) {
+                labels.push(label);
+                positions.push(at);
+            }
+        }
+    }
+    positions
+}
+
+/// Where each image goes: on its own line after the first line mentioning
+/// its position, or at the end if no line does. Images already in the note
+/// are left out.
+fn place_images(note: &Path, content: &str, positions: &[f64]) -> Vec<NoteLine> {
+    let mut pending = positions
+        .iter()
+        .map(|at| (file_label(*at), image_line(note, *at)))
+        .filter(|(_, image)| !content.contains(image.as_str()))
+        .collect::<Vec<(String, String)>>();
+    let mut placed = Vec::new();
+    for line in content.lines() {
+        if is_media_line(line) {
+            continue;
+        }
+        for at in find_timestamps(line) {
+            let label = file_label(at);
+            if let Some(index) = pending.iter().position(|(pending, _)| *pending == label) {
+                placed.push(NoteLine {
+                    line: pending.remove(index).1,
+                    after: Some(line.to_string()),
+                });
+            }
+        }
+    }
+    placed.extend(pending.into_iter().map(|(_, image)| NoteLine {
+        line: image,
+        after: None,
+    }));
+    placed
+}
+
+/// Grabs the frames of `source` at `positions` into the note's attachments
+/// folder and hands the images to the extension as note lines of `job_id`.
+/// A failed frame doesn't stop the others, but fails the job.
+pub async fn take(
+    settings: &FfmpegSettings,
+    log: &JobLog,
+    jobs: &Mutex<Jobs>,
+    job_id: JobId,
+    source: &Path,
+    note: &Path,
+    positions: &[f64],
+) -> Result<(), String> {
+    let dir = attachments_dir(note);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let mut taken = Vec::new();
+    for at in positions {
+        let output = dir.join(format!("{}.jpg", file_label(*at)));
+        match ffmpeg::snapshot(settings, log, source, *at, &output).await {
+            Ok(()) => taken.push(*at),
+            Err(err) => {
+                log.line(&format!(
+                    "Snapshot at {} failed: {}",
+                    format_timestamp(*at),
+                    err
+                ))
+                .await?
+            }
+        }
+    }
+
+    // Read the note only now, so lines typed while ffmpeg ran can be placed after
+    let content = tokio::fs::read_to_string(note)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", note.display(), e))?;
+    let placed = place_images(note, &content, &taken);
+    log.line(&format!(
+        "Took {} of {} snapshots for {}",
+        taken.len(),
+        positions.len(),
+        note.display()
+    ))
+    .await?;
+    let mut jobs = jobs.lock().await;
+    for line in placed {
+        jobs.add_note_line(job_id, line);
+    }
+    drop(jobs);
+    if taken.len() < positions.len() {
+        return Err(format!(
+            "{} of {} snapshots failed",
+            positions.len() - taken.len(),
+            positions.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_images_after_their_first_mention() {
+        let note = Path::new("/notes/2024/01/01/video.txt");
+        let content = "intro\nsee the diagram at 1:05\nagain at 1:05\n![0:10](<video.attachments/0m10s.jpg>)\n";
+        let placed = place_images(note, content, &[65.0, 10.0, 300.0]);
+        let placed = placed
+            .iter()
+            .map(|placed| (placed.line.as_str(), placed.after.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placed,
+            vec![
+                (
+                    "![1:05](<video.attachments/1m05s.jpg>)",
+                    Some("see the diagram at 1:05")
+                ),
+                ("![5:00](<video.attachments/5m00s.jpg>)", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn clips_have_no_missing_positions() {
+        let note = Path::new("/notes/2024/01/01/video.txt");
+        let content = "\
+[✂ 1:05–2:10](</videos/Talk (aaaaaaaaaaa) 1m05.000s-2m10.000s.mp4>)
+the good part is at 1:30
+";
+        assert_eq!(missing_positions(note, content), vec![90.0]);
+        let placed = place_images(note, content, &[65.0]);
+        assert_eq!(placed[0].after, None);
+    }
+}
@@ -0,0 +1,821 @@
+use crate::downloads::DownloadContext;
+use crate::jobs;
+use crate::jobs::JobId;
+use crate::jobs::JobStarted;
+use crate::metadata::PlaylistEntry;
+use crate::settings::Settings;
+use crate::youtube;
+use crate::youtube::YoutubeUrl;
+use chrono::DateTime;
+use chrono::Local;
+use hyper::Body;
+use hyper::Method;
+use hyper::Request;
+use hyper::Response;
+use hyper::StatusCode;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::error;
+use tracing::info;
+use tracing::warn;
+
+pub type SubscriptionId = u64;
+
+/// The `subscriptions` section of the settings file.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct SubscriptionSettings {
+    /// How often every subscription is checked for new entries. Zero turns
+    /// periodic checks off; `POST /subscriptions/{id}/check` still works.
+    pub check_interval_minutes: u64,
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        SubscriptionSettings {
+            check_interval_minutes: 60,
+        }
+    }
+}
+
+/// Which entries of a feed get downloaded.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Filter {
+    /// Only titles matching this regex.
+    pub title_regex: Option<String>,
+    /// In seconds.
+    pub min_duration: Option<f64>,
+    /// In seconds.
+    pub max_duration: Option<f64>,
+}
+
+impl Filter {
+    fn title_regex(&self) -> Result<Option<Regex>, String> {
+        self.title_regex
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| format!("Invalid title_regex {:?}: {}", pattern, e))
+            })
+            .transpose()
+    }
+
+    fn needs_duration(&self) -> bool {
+        self.min_duration.is_some() || self.max_duration.is_some()
+    }
+
+    /// Why an entry is filtered out, if it is.
+    fn rejects(
+        &self,
+        title_regex: Option<&Regex>,
+        title: Option<&str>,
+        duration: Option<f64>,
+    ) -> Option<String> {
+        if let Some(regex) = title_regex {
+            if !regex.is_match(title.unwrap_or_default()) {
+                return Some(format!(
+                    "title {:?} does not match",
+                    title.unwrap_or_default()
+                ));
+            }
+        }
+        match duration {
+            Some(duration) if self.min_duration.is_some_and(|min| duration < min) => {
+                Some(format!("{}s is too short", duration))
+            }
+            Some(duration) if self.max_duration.is_some_and(|max| duration > max) => {
+                Some(format!("{}s is too long", duration))
+            }
+            None if self.needs_duration() => Some("duration is unknown".to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Body of `POST /subscriptions`.
+#[derive(Deserialize, Debug)]
+pub struct NewSubscription {
+    /// A playlist URL, or a channel URL by `@handle` or channel id.
+    pub url: String,
+    /// Defaults to `video`.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub filter: Filter,
+    /// Also download what the feed already has, rather than only entries
+    /// that show up after subscribing.
+    #[serde(default)]
+    pub backfill: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Subscription {
+    pub id: SubscriptionId,
+    /// Canonical playlist or channel URL.
+    pub url: String,
+    pub profile: String,
+    pub filter: Filter,
+    pub backfill: bool,
+    pub created: DateTime<Local>,
+    /// When the feed was last fetched successfully.
+    pub last_checked: Option<DateTime<Local>>,
+    /// Why the last check failed, cleared by the next successful one.
+    pub last_error: Option<String>,
+    /// The job that downloaded the most recent batch of new entries.
+    pub last_job: Option<JobId>,
+    /// Entry ids that have been dealt with: downloaded, filtered out, already
+    /// in the catalog, or in the feed before the subscription was made.
+    pub seen: BTreeSet<String>,
+}
+
+/// What one check of a subscription found.
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub subscription: SubscriptionId,
+    /// Entries in the feed.
+    pub entries: usize,
+    /// Entries that had not been seen before.
+    pub new: Vec<String>,
+    /// The new entries being downloaded.
+    pub queued: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<JobStarted>,
+}
+
+/// Followed channels and playlists, persisted as JSON in the data dir.
+pub struct Subscriptions {
+    path: PathBuf,
+    next_id: SubscriptionId,
+    subscriptions: BTreeMap<SubscriptionId, Subscription>,
+    /// Entry ids claimed by a check that are being looked at or downloaded.
+    /// Not persisted, so a restart simply finds them new again.
+    pending: BTreeMap<SubscriptionId, BTreeSet<String>>,
+}
+
+impl Subscriptions {
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let list: Vec<Subscription> = if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("failed to parse {}: {}", path.display(), e))?
+        } else {
+            Vec::new()
+        };
+        let next_id = list.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+        Ok(Subscriptions {
+            path,
+            next_id,
+            subscriptions: list.into_iter().map(|s| (s.id, s)).collect(),
+            pending: BTreeMap::new(),
+        })
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let tmp = self.path.with_extension("json.tmp");
+        let list = self.subscriptions.values().collect::<Vec<_>>();
+        let content = serde_json::to_string_pretty(&list).unwrap();
+        std::fs::write(&tmp, content)
+            .map_err(|e| format!("failed to write {}: {}", tmp.display(), e))?;
+        std::fs::rename(&tmp, &self.path)
+            .map_err(|e| format!("failed to replace {}: {}", self.path.display(), e))
+    }
+
+    pub fn get(&self, id: SubscriptionId) -> Option<&Subscription> {
+        self.subscriptions.get(&id)
+    }
+
+    pub fn list(&self) -> Vec<&Subscription> {
+        self.subscriptions.values().collect()
+    }
+
+    /// Validates and stores a new subscription.
+    pub fn add(
+        &mut self,
+        new: NewSubscription,
+        settings: &Settings,
+    ) -> Result<Subscription, String> {
+        let url = youtube::parse_feed_url(&new.url)?;
+        let profile = new.profile.unwrap_or_else(|| "video".to_string());
+        settings.profile(&profile)?;
+        new.filter.title_regex()?;
+        let subscription = Subscription {
+            id: self.next_id,
+            url: url.canonical(),
+            profile,
+            filter: new.filter,
+            backfill: new.backfill,
+            created: Local::now(),
+            last_checked: None,
+            last_error: None,
+            last_job: None,
+            seen: BTreeSet::new(),
+        };
+        self.next_id += 1;
+        info!("Subscribed to {} as {}", subscription.url, subscription.id);
+        self.subscriptions
+            .insert(subscription.id, subscription.clone());
+        self.save()?;
+        Ok(subscription)
+    }
+
+    pub fn remove(&mut self, id: SubscriptionId) -> Result<bool, String> {
+        let removed = self.subscriptions.remove(&id).is_some();
+        self.pending.remove(&id);
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Claims the `ids` that are neither seen nor pending and returns them.
+    /// They stay pending, which keeps overlapping checks from queueing an
+    /// entry twice, until [`Subscriptions::mark_seen`] or
+    /// [`Subscriptions::release`].
+    fn take_unseen(&mut self, id: SubscriptionId, ids: &[String]) -> Vec<String> {
+        let Some(subscription) = self.subscriptions.get(&id) else {
+            return Vec::new();
+        };
+        let pending = self.pending.entry(id).or_default();
+        ids.iter()
+            .filter(|entry| {
+                !subscription.seen.contains(*entry) && pending.insert(entry.to_string())
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Marks `ids` as dealt with for good.
+    fn mark_seen(&mut self, id: SubscriptionId, ids: &[String]) {
+        self.release(id, ids);
+        if let Some(subscription) = self.subscriptions.get_mut(&id) {
+            subscription.seen.extend(ids.iter().cloned());
+        }
+    }
+
+    /// Gives up the claim on `ids` so the next check tries them again.
+    fn release(&mut self, id: SubscriptionId, ids: &[String]) {
+        if let Some(pending) = self.pending.get_mut(&id) {
+            for entry in ids {
+                pending.remove(entry);
+            }
+        }
+    }
+
+    fn record_check(&mut self, id: SubscriptionId, result: &Result<(), String>) {
+        if let Some(subscription) = self.subscriptions.get_mut(&id) {
+            match result {
+                Ok(()) => {
+                    subscription.last_checked = Some(Local::now());
+                    subscription.last_error = None;
+                }
+                Err(err) => subscription.last_error = Some(err.clone()),
+            }
+        }
+    }
+}
+
+/// Fetches a subscription's feed and starts a job downloading the entries
+/// that are new and pass its filter. The very first check of a subscription
+/// without `backfill` only takes note of what is already there.
+pub async fn check(
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    downloads: DownloadContext,
+    settings: &Settings,
+    downloads_dir: PathBuf,
+    id: SubscriptionId,
+) -> Result<CheckResult, String> {
+    let subscription = subscriptions
+        .lock()
+        .await
+        .get(id)
+        .cloned()
+        .ok_or_else(|| format!("No subscription {}", id))?;
+    let profile = settings.profile(&subscription.profile)?.clone();
+    let title_regex = subscription.filter.title_regex()?;
+    let result = fetch_new(&subscriptions, &downloads, settings, &subscription).await;
+    let mut locked = subscriptions.lock().await;
+    locked.record_check(id, &result.as_ref().map(|_| ()).map_err(|e| e.clone()));
+    let saved = locked.save();
+    let (entries, new) = result?;
+    let new_ids = new.iter().map(|e| e.id.clone()).collect::<Vec<String>>();
+    if let Err(err) = saved {
+        locked.release(id, &new_ids);
+        return Err(err);
+    }
+    drop(locked);
+
+    let mut skipped = Vec::new();
+    let mut skipped_ids = Vec::new();
+    let mut queued = Vec::new();
+    // Entries filtered out only because their metadata could not be fetched
+    let mut retry = Vec::new();
+    let downloaded = downloads.downloaded(&new_ids, &profile).await;
+    for (entry, downloaded) in new.iter().zip(downloaded) {
+        let mut duration = entry.duration;
+        let mut metadata_failed = false;
+        if duration.is_none() && subscription.filter.needs_duration() {
+            // Flat playlists don't always carry durations
+            let url = YoutubeUrl::Video {
+                id: entry.id.clone(),
+            };
+            match downloads.downloader.metadata(&url).await {
+                Ok(metadata) => duration = metadata.duration,
+                Err(err) => {
+                    warn!("No duration for {}: {}", entry.id, err);
+                    metadata_failed = true;
+                }
+            }
+        }
+        let reason = if downloaded {
+            Some("already downloaded".to_string())
+        } else {
+            subscription
+                .filter
+                .rejects(title_regex.as_ref(), entry.title.as_deref(), duration)
+        };
+        match reason {
+            Some(reason) if metadata_failed => {
+                skipped.push(format!(
+                    "Skipping {} until the next check: {}",
+                    entry.id, reason
+                ));
+                retry.push(entry.id.clone());
+            }
+            Some(reason) => {
+                skipped.push(format!("Skipping {}: {}", entry.id, reason));
+                skipped_ids.push(entry.id.clone());
+            }
+            None => queued.push(entry.id.clone()),
+        }
+    }
+    {
+        let mut locked = subscriptions.lock().await;
+        locked.release(id, &retry);
+        locked.mark_seen(id, &skipped_ids);
+        if let Err(err) = locked.save() {
+            locked.release(id, &queued);
+            return Err(err);
+        }
+    }
+    info!(
+        "Subscription {} has {} entries, {} new, {} to download",
+        id,
+        entries,
+        new.len(),
+        queued.len()
+    );
+
+    let mut result = CheckResult {
+        subscription: id,
+        entries,
+        new: new_ids,
+        queued: queued.clone(),
+        job: None,
+    };
+    if queued.is_empty() {
+        return Ok(result);
+    }
+
+    let dir = match crate::get_dated_dir(&downloads_dir) {
+        Ok(dir) => dir,
+        Err(e) => {
+            subscriptions.lock().await.release(id, &queued);
+            return Err(format!("Error getting dated dir: {}", e));
+        }
+    };
+    let jobs = downloads.jobs.clone();
+    let job = {
+        let mut jobs = jobs.lock().await;
+        let job = jobs.start(&format!("subscription:{}", id), &subscription.url);
+        jobs.set_progress(job.id, result.new.len(), skipped.len());
+        job
+    };
+    {
+        let mut locked = subscriptions.lock().await;
+        if let Some(subscription) = locked.subscriptions.get_mut(&id) {
+            subscription.last_job = Some(job.id);
+        }
+        if let Err(err) = locked.save() {
+            locked.release(id, &queued);
+            return Err(err);
+        }
+    }
+    let parent_id = job.id;
+    let profile_name = subscription.profile.clone();
+    tokio::spawn(jobs::run(jobs, job.clone(), move |log| async move {
+        for line in &skipped {
+            log.line(line).await?;
+        }
+        let result = downloads
+            .download_children(parent_id, &log, &profile_name, &profile, &dir, &queued)
+            .await;
+        // Only entries that made it into the catalog are seen, anything else
+        // is tried again by the next check
+        let mut locked = subscriptions.lock().await;
+        let failed = match result {
+            Ok(failed) => failed,
+            Err(err) => {
+                locked.release(id, &queued);
+                return Err(err);
+            }
+        };
+        let downloaded = queued
+            .iter()
+            .filter(|entry| !failed.contains(entry))
+            .cloned()
+            .collect::<Vec<String>>();
+        locked.mark_seen(id, &downloaded);
+        locked.release(id, &failed);
+        locked.save()?;
+        drop(locked);
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} of {} downloads failed and will be retried on the next check",
+            failed.len(),
+            queued.len()
+        ))
+    }));
+    result.job = Some(JobStarted::new(&job, None));
+    Ok(result)
+}
+
+/// Returns the number of entries in the feed and the ones not seen before,
+/// claiming them as pending.
+async fn fetch_new(
+    subscriptions: &Mutex<Subscriptions>,
+    downloads: &DownloadContext,
+    settings: &Settings,
+    subscription: &Subscription,
+) -> Result<(usize, Vec<PlaylistEntry>), String> {
+    settings.profile(&subscription.profile)?;
+    let url = youtube::parse_feed_url(&subscription.url)?;
+    let feed = downloads.downloader.playlist(&url).await?;
+    let ids = feed
+        .entries
+        .iter()
+        .map(|entry| entry.id.clone())
+        .filter(|id| youtube::is_video_id(id))
+        .collect::<Vec<String>>();
+    let first_check = subscription.last_checked.is_none();
+    let mut locked = subscriptions.lock().await;
+    let unseen = locked.take_unseen(subscription.id, &ids);
+    if first_check && !subscription.backfill {
+        locked.mark_seen(subscription.id, &unseen);
+        info!(
+            "First check of subscription {}, not downloading the {} existing entries",
+            subscription.id,
+            unseen.len()
+        );
+        return Ok((feed.entries.len(), Vec::new()));
+    }
+    drop(locked);
+    let entries = feed.entries.len();
+    let new = feed
+        .entries
+        .into_iter()
+        .filter(|entry| unseen.contains(&entry.id))
+        .collect();
+    Ok((entries, new))
+}
+
+/// Checks every subscription each `check_interval_minutes`, starting right
+/// away.
+pub async fn check_periodically(
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    downloads: DownloadContext,
+    settings: Settings,
+    downloads_dir: PathBuf,
+) {
+    let minutes = settings.subscriptions.check_interval_minutes;
+    if minutes == 0 {
+        info!("Periodic subscription checks are off");
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(minutes * 60));
+    loop {
+        interval.tick().await;
+        let ids = subscriptions
+            .lock()
+            .await
+            .list()
+            .iter()
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        for id in ids {
+            let result = check(
+                subscriptions.clone(),
+                downloads.clone(),
+                &settings,
+                downloads_dir.clone(),
+                id,
+            )
+            .await;
+            if let Err(err) = result {
+                error!("Error checking subscription {}: {}", id, err);
+            }
+        }
+    }
+}
+
+/// Serves `GET /subscriptions`, `POST /subscriptions`,
+/// `GET /subscriptions/{id}`, `DELETE /subscriptions/{id}` and
+/// `POST /subscriptions/{id}/check`.
+pub async fn handle(
+    req: Request<Body>,
+    subscriptions: Arc<Mutex<Subscriptions>>,
+    downloads: DownloadContext,
+    settings: &Settings,
+    downloads_dir: PathBuf,
+) -> Response<Body> {
+    let segments = req
+        .uri()
+        .path()
+        .trim_matches('/')
+        .split('/')
+        .skip(1)
+        .map(String::from)
+        .collect::<Vec<String>>();
+    let id = match segments.first().map(|id| id.parse::<SubscriptionId>()) {
+        None => None,
+        Some(Ok(id)) => Some(id),
+        Some(Err(_)) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body("Invalid subscription id".into())
+                .unwrap();
+        }
+    };
+    let rest = segments
+        .iter()
+        .skip(1)
+        .map(String::as_str)
+        .collect::<Vec<&str>>();
+
+    match (req.method(), id, rest.as_slice()) {
+        (&Method::GET, None, []) => {
+            let subscriptions = subscriptions.lock().await;
+            Response::new(serde_json::to_string(&subscriptions.list()).unwrap().into())
+        }
+        (&Method::POST, None, []) => {
+            let whole_body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+            let new: NewSubscription = match serde_json::from_slice(&whole_body) {
+                Ok(it) => it,
+                Err(err) => {
+                    return Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(format!("Invalid subscription: {}", err).into())
+                        .unwrap();
+                }
+            };
+            match subscriptions.lock().await.add(new, settings) {
+                Ok(subscription) => {
+                    Response::new(serde_json::to_string(&subscription).unwrap().into())
+                }
+                Err(err) => {
+                    error!("Rejecting subscription: {}", err);
+                    Response::builder()
+                        .status(StatusCode::BAD_REQUEST)
+                        .body(err.into())
+                        .unwrap()
+                }
+            }
+        }
+        (&Method::GET, Some(id), []) => match subscriptions.lock().await.get(id) {
+            Some(subscription) => {
+                Response::new(serde_json::to_string(subscription).unwrap().into())
+            }
+            None => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("No such subscription".into())
+                .unwrap(),
+        },
+        (&Method::DELETE, Some(id), []) => match subscriptions.lock().await.remove(id) {
+            Ok(true) => Response::new("Unsubscribed".into()),
+            Ok(false) => Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body("No such subscription".into())
+                .unwrap(),
+            Err(err) => {
+                error!("Error removing subscription: {}", err);
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Error removing subscription".into())
+                    .unwrap()
+            }
+        },
+        (&Method::POST, Some(id), ["check"]) => {
+            if subscriptions.lock().await.get(id).is_none() {
+                return Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body("No such subscription".into())
+                    .unwrap();
+            }
+            match check(subscriptions, downloads, settings, downloads_dir, id).await {
+                Ok(result) => Response::new(serde_json::to_string(&result).unwrap().into()),
+                Err(err) => {
+                    error!("Error checking subscription {}: {}", id, err);
+                    Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(err.into())
+                        .unwrap()
+                }
+            }
+        }
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body("".into())
+            .unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::catalog::Catalog;
+    use crate::downloader;
+    use crate::downloader::DownloaderSettings;
+    use crate::downloader::FakeSettings;
+    use crate::jobs::Jobs;
+
+    const PLAYLIST: &str = "PLfakefakefakefakefake";
+
+    struct Fixture {
+        dir: PathBuf,
+        subscriptions: Arc<Mutex<Subscriptions>>,
+        downloads: DownloadContext,
+        settings: Settings,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "onboarder-subscriptions-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            for sub_dir in ["fixtures", "downloads", "notes", "logs"] {
+                std::fs::create_dir_all(dir.join(sub_dir)).unwrap();
+            }
+            let downloader = downloader::create(&DownloaderSettings::Fake(FakeSettings {
+                step_delay_ms: 0,
+                fixtures_dir: Some(dir.join("fixtures")),
+                ..FakeSettings::default()
+            }));
+            let downloads = DownloadContext {
+                jobs: Arc::new(Mutex::new(Jobs::new(dir.join("logs")).unwrap())),
+                downloader,
+                catalog: Arc::new(Mutex::new(Catalog::load(dir.join("catalog.json")).unwrap())),
+                search_dirs: vec![dir.join("downloads")],
+                notes_dir: dir.join("notes"),
+            };
+            let subscriptions = Subscriptions::load(dir.join("subscriptions.json")).unwrap();
+            Fixture {
+                dir,
+                subscriptions: Arc::new(Mutex::new(subscriptions)),
+                downloads,
+                settings: Settings::default(),
+            }
+        }
+
+        fn write_fixture(&self, name: &str, content: &str) {
+            std::fs::write(self.dir.join("fixtures").join(name), content).unwrap();
+        }
+
+        async fn subscribe(&self, filter: Filter) -> SubscriptionId {
+            let new = NewSubscription {
+                url: format!("https://www.youtube.com/playlist?list={}", PLAYLIST),
+                profile: None,
+                filter,
+                backfill: true,
+            };
+            let mut subscriptions = self.subscriptions.lock().await;
+            subscriptions.add(new, &self.settings).unwrap().id
+        }
+
+        async fn check(&self, id: SubscriptionId) -> CheckResult {
+            check(
+                self.subscriptions.clone(),
+                self.downloads.clone(),
+                &self.settings,
+                self.dir.join("downloads"),
+                id,
+            )
+            .await
+            .unwrap()
+        }
+
+        async fn wait(&self, job: JobId) {
+            loop {
+                let finished = self.downloads.jobs.lock().await.get(job).unwrap().finished;
+                if finished.is_some() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        async fn seen(&self, id: SubscriptionId) -> Vec<String> {
+            let subscriptions = self.subscriptions.lock().await;
+            subscriptions
+                .get(id)
+                .unwrap()
+                .seen
+                .iter()
+                .cloned()
+                .collect()
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_entries_without_metadata() {
+        let fixture = Fixture::new("metadata");
+        fixture.write_fixture(
+            &format!("{}.playlist.json", PLAYLIST),
+            r#"{"id": "PLfakefakefakefakefake", "entries": [
+                {"id": "aaaaaaaaaaa", "title": "Long enough", "duration": 700},
+                {"id": "bbbbbbbbbbb", "title": "Too short", "duration": 10},
+                {"id": "ccccccccccc", "title": "Unknown length"}
+            ]}"#,
+        );
+        // Unparseable, so fetching its metadata fails
+        fixture.write_fixture("ccccccccccc.info.json", "{");
+        let id = fixture
+            .subscribe(Filter {
+                min_duration: Some(60.0),
+                ..Filter::default()
+            })
+            .await;
+
+        let result = fixture.check(id).await;
+        assert_eq!(result.entries, 3);
+        assert_eq!(result.queued, vec!["aaaaaaaaaaa"]);
+        // Queued entries are only seen once they are downloaded, so a restart
+        // before then finds them new again
+        let saved = Subscriptions::load(fixture.dir.join("subscriptions.json")).unwrap();
+        let saved_seen = saved.get(id).unwrap().seen.iter().collect::<Vec<_>>();
+        assert_eq!(saved_seen, vec!["bbbbbbbbbbb"]);
+        fixture.wait(result.job.unwrap().job_id).await;
+        assert_eq!(fixture.seen(id).await, vec!["aaaaaaaaaaa", "bbbbbbbbbbb"]);
+
+        fixture.write_fixture(
+            "ccccccccccc.info.json",
+            r#"{"id": "ccccccccccc", "title": "Unknown length", "duration": 900}"#,
+        );
+        let result = fixture.check(id).await;
+        assert_eq!(result.new, vec!["ccccccccccc"]);
+        assert_eq!(result.queued, vec!["ccccccccccc"]);
+    }
+
+    #[tokio::test]
+    async fn first_check_without_backfill_only_takes_note() {
+        let fixture = Fixture::new("backfill");
+        fixture.write_fixture(
+            &format!("{}.playlist.json", PLAYLIST),
+            r#"{"id": "PLfakefakefakefakefake", "entries": [
+                {"id": "aaaaaaaaaaa", "title": "Old", "duration": 700}
+            ]}"#,
+        );
+        let id = fixture.subscribe(Filter::default()).await;
+        fixture
+            .subscriptions
+            .lock()
+            .await
+            .subscriptions
+            .get_mut(&id)
+            .unwrap()
+            .backfill = false;
+
+        let result = fixture.check(id).await;
+        assert!(result.new.is_empty());
+        assert!(result.job.is_none());
+
+        fixture.write_fixture(
+            &format!("{}.playlist.json", PLAYLIST),
+            r#"{"id": "PLfakefakefakefakefake", "entries": [
+                {"id": "ddddddddddd", "title": "New", "duration": 700},
+                {"id": "aaaaaaaaaaa", "title": "Old", "duration": 700}
+            ]}"#,
+        );
+        let result = fixture.check(id).await;
+        assert_eq!(result.entries, 2);
+        assert_eq!(result.queued, vec!["ddddddddddd"]);
+        assert!(result.job.is_some());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::catalog::CatalogEntry;
+use crate::catalog::FileKind;
+use crate::timestamps::parse_timestamp;
+use regex::Regex;
+use serde::Serialize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// A stretch of speech, with positions in seconds.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// A cue as it appears in the file, before rolling duplicates are removed.
+struct Cue {
+    start: f64,
+    end: f64,
+    lines: Vec<String>,
+}
+
+/// Reads a `.vtt` or `.srt` file into clean segments.
+pub fn parse_file(path: &Path) -> Result<Vec<Segment>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(parse(&content))
+}
+
+/// Parses WebVTT or SRT, which differ little enough to share a parser: SRT
+/// has numbered cues and commas in its times, WebVTT has a header, notes,
+/// styles and cue settings. YouTube's automatic captions roll, repeating
+/// the previous line in each cue, and those repeats are merged away.
+pub fn parse(content: &str) -> Vec<Segment> {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let cues = content
+        .split("\n\n")
+        .filter_map(parse_cue)
+        .collect::<Vec<Cue>>();
+    merge_rolling(cues)
+}
+
+fn parse_cue(block: &str) -> Option<Cue> {
+    let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+    // The timing line, after an optional cue id (always there in SRT)
+    let timing = lines.by_ref().find(|line| line.contains("-->"))?;
+    let (start, rest) = timing.split_once("-->")?;
+    // WebVTT cue settings like `align:start position:0%` follow the end time
+    let end = rest.split_whitespace().next()?;
+    let start = parse_cue_time(start)?;
+    let end = parse_cue_time(end)?;
+    let lines = lines
+        .map(clean_line)
+        .filter(|line| !line.is_empty())
+        .collect();
+    Some(Cue { start, end, lines })
+}
+
+fn parse_cue_time(text: &str) -> Option<f64> {
+    parse_timestamp(&text.trim().replace(',', "."))
+}
+
+/// Drops markup: WebVTT's word timings and classes, SRT's `<i>` and
+/// `{\an8}`, and entities.
+fn clean_line(line: &str) -> String {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    let markup = MARKUP.get_or_init(|| Regex::new(r"<[^>]*>|\{\\[^}]*\}").unwrap());
+    let text = markup
+        .replace_all(line, "")
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// One segment per distinct line. A line that the cues right before also
+/// showed only extends the segment already made for it.
+fn merge_rolling(cues: Vec<Cue>) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for cue in cues {
+        for line in cue.lines {
+            let shown = segments
+                .iter_mut()
+                .rev()
+                .take(2)
+                .find(|segment| segment.text == line && segment.end + 0.5 >= cue.start);
+            match shown {
+                Some(segment) => segment.end = segment.end.max(cue.end),
+                None => segments.push(Segment {
+                    start: cue.start,
+                    end: cue.end,
+                    text: line,
+                }),
+            }
+        }
+    }
+    // A repeated line was kept up until its last repeat, past the next line's start
+    for index in 1..segments.len() {
+        let next_start = segments[index].start;
+        let previous = &mut segments[index - 1];
+        if next_start > previous.start {
+            previous.end = previous.end.min(next_start);
+        }
+    }
+    segments
+}
+
+/// Whether `path` is a subtitle file this module reads, and not a notes
+/// overlay written by [`crate::overlay`].
+pub fn is_transcript_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    (name.ends_with(".vtt") || name.ends_with(".srt")) && !name.contains(".notes.")
+}
+
+/// The language code in a yt-dlp subtitle file name, like `en` in
+/// `Title [id].en.vtt`.
+pub fn language(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, language) = stem.rsplit_once('.')?;
+    (!language.is_empty() && language.len() <= 16 && !language.contains(' '))
+        .then(|| language.to_string())
+}
+
+/// The subtitle files of a catalogued video that are still on disk, newest
+/// first.
+pub fn transcript_files(entry: &CatalogEntry) -> Vec<PathBuf> {
+    let mut files = entry
+        .files
+        .iter()
+        .filter(|file| {
+            file.kind == FileKind::Subtitle && is_transcript_file(&file.path) && file.path.exists()
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|file| std::cmp::Reverse(file.modified));
+    files.into_iter().map(|file| file.path.clone()).collect()
+}
+
+/// The file in `files` with language `lang`, or the first (newest) one.
+pub fn pick(files: &[PathBuf], lang: Option<&str>) -> Option<PathBuf> {
+    match lang {
+        Some(lang) => files
+            .iter()
+            .find(|file| language(file).as_deref() == Some(lang)),
+        None => files.first(),
+    }
+    .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(start: f64, end: f64, text: &str) -> Segment {
+        Segment {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    /// Automatic captions as yt-dlp saves them from YouTube: every cue shows
+    /// the previous line again above the new one, with word timings, and
+    /// 10ms cues in between hold the finished line.
+    const AUTO_VTT: &str = "WEBVTT
+Kind: captions
+Language: en
+
+00:00:00.000 --> 00:00:02.310 align:start position:0%
+ 
+so<00:00:00.320><c> today</c><00:00:00.640><c> we're</c><00:00:00.880><c> going</c><00:00:01.040><c> to</c>
+
+00:00:02.310 --> 00:00:02.320 align:start position:0%
+so today we're going to
+ 
+
+00:00:02.320 --> 00:00:05.190 align:start position:0%
+so today we're going to
+talk<00:00:02.560><c> about</c><00:00:02.800><c> rust</c>
+
+00:00:05.190 --> 00:00:05.200 align:start position:0%
+talk about rust
+ 
+
+00:00:05.200 --> 00:00:07.500 align:start position:0%
+talk about rust
+and<00:00:05.520><c> lifetimes</c>
+";
+
+    #[test]
+    fn merges_rolling_auto_captions() {
+        assert_eq!(
+            parse(AUTO_VTT),
+            vec![
+                segment(0.0, 2.32, "so today we're going to"),
+                segment(2.32, 5.2, "talk about rust"),
+                segment(5.2, 7.5, "and lifetimes"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_lines_repeated_later() {
+        let vtt = "WEBVTT
+
+00:00:01.000 --> 00:00:02.000
+yes
+
+00:00:02.000 --> 00:00:03.000
+no
+
+00:00:10.000 --> 00:00:11.000
+yes
+";
+        assert_eq!(
+            parse(vtt),
+            vec![
+                segment(1.0, 2.0, "yes"),
+                segment(2.0, 3.0, "no"),
+                segment(10.0, 11.0, "yes"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_srt() {
+        let srt = "\u{feff}1\r
+00:00:01,000 --> 00:00:03,500\r
+<i>Hello</i> there\r
+\r
+2\r
+00:00:04,000 --> 00:00:06,000\r
+{\\an8}General &amp; Kenobi\r
+second line\r
+\r
+3\r
+01:00:00,250 --> 01:00:02,000\r
+much later\r
+";
+        assert_eq!(
+            parse(srt),
+            vec![
+                segment(1.0, 3.5, "Hello there"),
+                segment(4.0, 6.0, "General & Kenobi"),
+                segment(4.0, 6.0, "second line"),
+                segment(3600.25, 3602.0, "much later"),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_vtt_notes_and_styles() {
+        let vtt = "WEBVTT
+
+STYLE
+::cue { color: yellow }
+
+NOTE made by hand
+
+intro
+00:01.000 --> 00:02.500 line:0
+<v Speaker>Hi &lt;there&gt;</v>
+";
+        assert_eq!(parse(vtt), vec![segment(1.0, 2.5, "Hi <there>")]);
+    }
+}
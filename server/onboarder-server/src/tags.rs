@@ -0,0 +1,230 @@
+use regex::Regex;
+use std::sync::OnceLock;
+
+fn hashtag_regex() -> &'static Regex {
+    static HASHTAG: OnceLock<Regex> = OnceLock::new();
+    // Not after a word character, `&`, `/` or `#`, so URL fragments and
+    // Markdown headings aren't tags
+    HASHTAG.get_or_init(|| Regex::new(r"(?:^|[^\w&/#])#([\p{L}\p{N}_][\p{L}\p{N}_/-]*)").unwrap())
+}
+
+/// Tags are compared without their `#` and case.
+pub fn normalize(tag: &str) -> String {
+    tag.trim()
+        .trim_start_matches('#')
+        .trim_matches(|c: char| c == '"' || c == '\'')
+        .trim()
+        .to_lowercase()
+}
+
+/// The YAML front matter of a note, between a leading `---` line and the
+/// next one.
+fn front_matter(content: &str) -> Option<&str> {
+    let rest = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))?;
+    let end = rest.find("\n---")?;
+    Some(&rest[..end])
+}
+
+/// The `tags` of the front matter, written as `tags: [a, b]`, `tags: a, b`
+/// or a list of `- a` lines.
+fn front_matter_tags(front_matter: &str) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut lines = front_matter.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(value) = line.strip_prefix("tags:") else {
+            continue;
+        };
+        let value = value.trim().trim_start_matches('[').trim_end_matches(']');
+        tags.extend(value.split(',').map(normalize));
+        while let Some(item) = lines.next_if(|line| line.trim_start().starts_with("- ")) {
+            tags.push(normalize(item.trim_start().trim_start_matches("- ")));
+        }
+    }
+    tags
+}
+
+/// Hashtags that are only digits are issue numbers and the like, not tags.
+fn is_number(tag: &str) -> bool {
+    tag.chars().all(|c| c.is_ascii_digit())
+}
+
+/// The tags of a note: `#hashtags` anywhere in it and the `tags` of its
+/// front matter, normalized, sorted and without duplicates.
+pub fn extract(content: &str) -> Vec<String> {
+    let mut tags = hashtag_regex()
+        .captures_iter(content)
+        .map(|captures| normalize(&captures[1]))
+        .filter(|tag| !is_number(tag))
+        .collect::<Vec<String>>();
+    if let Some(front_matter) = front_matter(content) {
+        tags.extend(front_matter_tags(front_matter));
+    }
+    tags.retain(|tag| !tag.is_empty());
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// Whether `tag` can be written as a hashtag and read back as the same tag.
+pub fn is_valid(tag: &str) -> bool {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    let valid = TAG.get_or_init(|| Regex::new(r"^[\p{L}\p{N}_][\p{L}\p{N}_/-]*$").unwrap());
+    valid.is_match(tag) && !is_number(tag) && normalize(tag) == tag
+}
+
+/// `item` with its tag replaced by `to` if it is `from`, keeping the
+/// brackets, spaces and line ending around it.
+fn rename_item(item: &str, from: &str, to: &str) -> String {
+    let core = item.trim_start_matches([' ', '\t', '[']);
+    let prefix = &item[..item.len() - core.len()];
+    let core = core.trim_end_matches([' ', '\t', ']', '\r', '\n']);
+    let suffix = &item[prefix.len() + core.len()..];
+    if normalize(core) == from {
+        format!("{}{}{}", prefix, to, suffix)
+    } else {
+        item.to_string()
+    }
+}
+
+fn rename_hashtags(line: &str, from: &str, to: &str) -> String {
+    let mut renamed = String::with_capacity(line.len());
+    let mut at = 0;
+    for captures in hashtag_regex().captures_iter(line) {
+        let tag = captures.get(1).unwrap();
+        if normalize(tag.as_str()) == from && !is_number(from) {
+            renamed.push_str(&line[at..tag.start()]);
+            renamed.push_str(to);
+            at = tag.end();
+        }
+    }
+    renamed.push_str(&line[at..]);
+    renamed
+}
+
+/// `content` with tag `from` renamed to `to` wherever [`extract`] would
+/// find it, or `None` if it isn't there. Everything else is left as it was.
+/// Both tags must be normalized.
+pub fn rename(content: &str, from: &str, to: &str) -> Option<String> {
+    let front_matter = front_matter(content).map(|front_matter| {
+        let start = front_matter.as_ptr() as usize - content.as_ptr() as usize;
+        start..start + front_matter.len()
+    });
+    let mut renamed = String::with_capacity(content.len());
+    let mut at = 0;
+    let mut in_tags = false;
+    for line in content.split_inclusive('\n') {
+        let in_front_matter = front_matter
+            .as_ref()
+            .is_some_and(|front_matter| front_matter.contains(&at));
+        at += line.len();
+        if in_front_matter {
+            if let Some(value) = line.strip_prefix("tags:") {
+                let items = value
+                    .split(',')
+                    .map(|item| rename_item(item, from, to))
+                    .collect::<Vec<String>>();
+                renamed.push_str("tags:");
+                renamed.push_str(&items.join(","));
+                in_tags = true;
+                continue;
+            }
+            let item = line.trim_start();
+            if in_tags && item.starts_with("- ") {
+                renamed.push_str(&line[..line.len() - item.len() + 2]);
+                renamed.push_str(&rename_item(&item[2..], from, to));
+                continue;
+            }
+            in_tags = false;
+        }
+        renamed.push_str(&rename_hashtags(line, from, to));
+    }
+    (renamed != content).then_some(renamed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOTE: &str = "\
+---
+title: A talk
+tags: [Rust, 'web dev', rust/async]
+---
+# Heading, not a tag
+Notes on #Rust and #rust/async, see https://example.com/page#rust and issue #123.
+Also a&#rust entity and ##double.
+";
+
+    #[test]
+    fn extracts_hashtags_and_front_matter() {
+        assert_eq!(extract(NOTE), vec!["rust", "rust/async", "web dev"]);
+        let listed = "---\ntags:\n  - Go\n  - \"tools\"\nother: x\n- not a tag\n---\n#go too\n";
+        assert_eq!(extract(listed), vec!["go", "tools"]);
+        assert_eq!(
+            extract("tags: [not, front, matter]\n#ünïcode_1-x"),
+            vec!["ünïcode_1-x"]
+        );
+        assert!(extract("#123 #4 plain").is_empty());
+    }
+
+    #[test]
+    fn valid_tags_read_back_the_same() {
+        assert!(is_valid("rust"));
+        assert!(is_valid("rust/async"));
+        assert!(is_valid("web-dev_2"));
+        assert!(is_valid("ünïcode"));
+        assert!(!is_valid("Rust"));
+        assert!(!is_valid("web dev"));
+        assert!(!is_valid("#rust"));
+        assert!(!is_valid("/rust"));
+        assert!(!is_valid("123"));
+        assert!(!is_valid(""));
+    }
+
+    #[test]
+    fn renames_items_keeping_what_is_around_them() {
+        assert_eq!(rename_item(" Rust]\n", "rust", "go"), " go]\n");
+        assert_eq!(rename_item("[rust", "rust", "go"), "[go");
+        assert_eq!(rename_item("  - rust\r\n", "rust", "go"), "  - rust\r\n");
+        assert_eq!(rename_item(" 'Rust' ", "rust", "go"), " go ");
+        assert_eq!(rename_item(" rusty", "rust", "go"), " rusty");
+    }
+
+    #[test]
+    fn renames_wherever_extract_finds_the_tag() {
+        assert_eq!(
+            rename(NOTE, "rust", "rustlang").unwrap(),
+            "\
+---
+title: A talk
+tags: [rustlang, 'web dev', rust/async]
+---
+# Heading, not a tag
+Notes on #rustlang and #rust/async, see https://example.com/page#rust and issue #123.
+Also a&#rust entity and ##double.
+"
+        );
+        // Nested tags are tags of their own
+        let renamed = rename(NOTE, "rust/async", "async").unwrap();
+        assert!(renamed.contains("tags: [Rust, 'web dev', async]\n"));
+        assert!(renamed.contains("#Rust and #async,"));
+        assert_eq!(extract(&renamed), vec!["async", "rust", "web dev"]);
+
+        let listed = "---\r\ntags:\r\n  - Go\r\n  - tools\r\n---\r\n#go\r\n";
+        assert_eq!(
+            rename(listed, "go", "golang").unwrap(),
+            "---\r\ntags:\r\n  - golang\r\n  - tools\r\n---\r\n#golang\r\n"
+        );
+    }
+
+    #[test]
+    fn renaming_a_missing_tag_changes_nothing() {
+        assert_eq!(rename(NOTE, "go", "golang"), None);
+        // Only digits and URL fragments aren't tags
+        assert_eq!(rename(NOTE, "123", "x"), None);
+        assert_eq!(rename("https://example.com/#go", "go", "golang"), None);
+        assert_eq!(rename("", "go", "golang"), None);
+    }
+}
@@ -0,0 +1,266 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+/// A position in a video, given either as seconds or as a timestamp like
+/// `12:34`.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Position {
+    Seconds(f64),
+    Timestamp(String),
+}
+
+impl Position {
+    pub fn seconds(&self) -> Result<f64, String> {
+        match self {
+            Position::Seconds(seconds) if seconds.is_finite() && *seconds >= 0.0 => Ok(*seconds),
+            Position::Seconds(seconds) => Err(format!("Invalid position: {}", seconds)),
+            Position::Timestamp(text) => {
+                parse_timestamp(text).ok_or_else(|| format!("Invalid timestamp: {:?}", text))
+            }
+        }
+    }
+}
+
+/// Formats a position in seconds the way YouTube shows it: `M:SS` below an
+/// hour, `H:MM:SS` above.
+pub fn format_timestamp(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// A watch URL that starts playing at `seconds`.
+pub fn deep_link(video_id: &str, seconds: f64) -> String {
+    format!(
+        "https://www.youtube.com/watch?v={}&t={}s",
+        video_id,
+        seconds.max(0.0) as u64
+    )
+}
+
+/// Parses `H:MM:SS`, `M:SS` or plain seconds, each with optional fractions.
+pub fn parse_timestamp(text: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    let parts = text.trim().split(':').collect::<Vec<&str>>();
+    if parts.len() > 3 {
+        return None;
+    }
+    for (index, part) in parts.iter().enumerate() {
+        let last = index == parts.len() - 1;
+        let value = if last {
+            part.parse::<f64>().ok()?
+        } else {
+            part.parse::<u64>().ok()? as f64
+        };
+        if !value.is_finite() || value < 0.0 || (index > 0 && value >= 60.0) {
+            return None;
+        }
+        seconds = seconds * 60.0 + value;
+    }
+    Some(seconds)
+}
+
+/// A position for use in file names, like `1h02m03s` or `12m34s`.
+pub fn file_label(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+    if hours > 0 {
+        format!("{}h{:02}m{:02}s", hours, minutes, seconds)
+    } else {
+        format!("{}m{:02}s", minutes, seconds)
+    }
+}
+
+/// [`file_label`] down to the millisecond, like `12m34.567s`, for files
+/// that may be made a moment apart.
+pub fn precise_file_label(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    let label = file_label((millis / 1000) as f64);
+    format!("{}.{:03}s", label.trim_end_matches('s'), millis % 1000)
+}
+
+/// The positions written in one line of a note, like `see diagram at 12:34`,
+/// in order. Lines from the extension (`video current time 00:12:34 /
+/// 00:45:00 (27.93%) at <date>`, or `<date> --- 00:12:34 / 00:45:00 (27.93%)
+/// --- paused`) only count their current time, as the rest is the duration
+/// and the wall clock. Its other lines, like `<date> --- Download started
+/// for ...`, have no position at all.
+pub fn find_timestamps(line: &str) -> Vec<f64> {
+    if let Some(progress) = progress_regex().captures(line) {
+        return parse_timestamp(&progress[1]).into_iter().collect();
+    }
+    if js_date_regex()
+        .find(line.trim_start())
+        .is_some_and(|date| date.start() == 0)
+    {
+        return Vec::new();
+    }
+    timestamp_regex()
+        .find_iter(&js_date_regex().replace_all(line, ""))
+        .filter_map(|found| parse_timestamp(found.as_str()))
+        .collect()
+}
+
+/// `line` with every timestamp in it removed.
+pub fn strip_timestamps(line: &str) -> String {
+    timestamp_regex().replace_all(line, "").into_owned()
+}
+
+fn timestamp_regex() -> &'static Regex {
+    static TIMESTAMP: OnceLock<Regex> = OnceLock::new();
+    TIMESTAMP.get_or_init(|| Regex::new(r"\b(?:\d{1,2}:)?\d{1,2}:\d{2}\b").unwrap())
+}
+
+/// How the extension writes the position: `00:12:34 / 00:45:00 (27.93%)`.
+fn progress_regex() -> &'static Regex {
+    static PROGRESS: OnceLock<Regex> = OnceLock::new();
+    PROGRESS.get_or_init(|| Regex::new(r"(\d{2}:\d{2}:\d{2}) / \S+ \([^)]*%\)").unwrap())
+}
+
+/// How the extension writes the wall clock, JavaScript's `Date.toString()`:
+/// `Sat Oct 19 2024 05:38:49 GMT-0400 (Eastern Daylight Time)`.
+fn js_date_regex() -> &'static Regex {
+    static JS_DATE: OnceLock<Regex> = OnceLock::new();
+    JS_DATE.get_or_init(|| {
+        Regex::new(
+            r"\b(?:Mon|Tue|Wed|Thu|Fri|Sat|Sun) (?:Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec) \d{2} \d{4} \d{2}:\d{2}:\d{2} GMT[+-]\d{4}(?: \([^)]*\))?",
+        )
+        .unwrap()
+    })
+}
+
+/// What a note line tied to a position is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LineKind {
+    /// `video current time ...` from the extension's Timestamp button. The
+    /// note itself is on the lines after it.
+    Timestamp,
+    /// `<date> --- <progress> --- paused` and the like, logged by the
+    /// extension as the video is played, liked and so on.
+    Event(String),
+    /// Any other line mentioning a position, like `see diagram at 12:34`.
+    Text,
+}
+
+#[derive(Clone, Debug)]
+pub struct TimedLine {
+    /// Index into the note's lines.
+    pub index: usize,
+    /// In seconds, the first position on the line.
+    pub position: f64,
+    pub kind: LineKind,
+}
+
+/// Whether `line` is a snapshot image or a clip link. The positions on
+/// these are the ones the file was made from, not something written in the
+/// note.
+pub fn is_media_line(line: &str) -> bool {
+    let line = line.trim_start();
+    line.starts_with("![") || line.starts_with("[✂")
+}
+
+/// The lines of a note that mention a position, in note order. Snapshot
+/// and clip lines are left out, see [`is_media_line`].
+pub fn timed_lines(content: &str) -> Vec<TimedLine> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !is_media_line(line))
+        .filter_map(|(index, line)| {
+            let position = *find_timestamps(line).first()?;
+            let parts = line.split(" --- ").collect::<Vec<&str>>();
+            let kind = if line.contains("video current time") {
+                LineKind::Timestamp
+            } else if parts.len() == 3 && progress_regex().is_match(parts[1]) {
+                LineKind::Event(parts[2].trim().to_string())
+            } else {
+                LineKind::Text
+            };
+            Some(TimedLine {
+                index,
+                position,
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// The non-empty text lines after `lines[index]` up to the next line with a
+/// position, which is where the note for a [`LineKind::Timestamp`] line goes.
+/// Snapshot and clip lines are skipped over.
+pub fn text_after<'a>(lines: &[&'a str], index: usize) -> Vec<&'a str> {
+    lines[index + 1..]
+        .iter()
+        .take_while(|line| is_media_line(line) || find_timestamps(line).is_empty())
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !is_media_line(line))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_labels() {
+        assert_eq!(file_label(754.9), "12m34s");
+        assert_eq!(file_label(3723.0), "1h02m03s");
+        assert_eq!(precise_file_label(754.9), "12m34.900s");
+        assert_eq!(precise_file_label(754.0), "12m34.000s");
+        assert_eq!(precise_file_label(3723.0005), "1h02m03.001s");
+    }
+
+    // Lines as the extension writes them
+    const DOWNLOAD: &str = "Sat Oct 19 2024 05:38:49 GMT-0400 (Eastern Daylight Time) --- Download started for \"Talk at 12:30 [dQw4w9WgXcQ].mp4\" (job 3, log: https://localhost:5876/jobs/3/log)";
+    const PLAYLIST: &str = "Sat Oct 19 2024 05:39:02 GMT-0400 (Eastern Daylight Time) --- Playlist download started (job 4, log: https://localhost:5876/jobs/4/log)";
+    const SUBTITLES: &str = "Sat Oct 19 2024 05:40:11 GMT-0400 (Eastern Daylight Time) --- Subtitles download started (job 5, log: https://localhost:5876/jobs/5/log)";
+    const PAUSED: &str = "Sat Oct 19 2024 05:41:10 GMT-0400 (Eastern Daylight Time) --- 00:02:13 / 00:10:00 (22.17%) --- paused";
+    const TIMESTAMP: &str = "video current time 00:02:05 / 00:10:00 (20.83%) at Sat Oct 19 2024 05:41:02 GMT-0400 (Eastern Daylight Time)";
+
+    #[test]
+    fn finds_timestamps() {
+        assert_eq!(
+            find_timestamps("see diagram at 12:34, again 1:02:03"),
+            vec![754.0, 3723.0]
+        );
+        assert_eq!(find_timestamps(PAUSED), vec![133.0]);
+        assert_eq!(find_timestamps(TIMESTAMP), vec![125.0]);
+        assert_eq!(
+            find_timestamps("at 1:05 (Sat Oct 19 2024 05:41:02 GMT-0400)"),
+            vec![65.0]
+        );
+    }
+
+    #[test]
+    fn extension_lines_without_progress_have_no_position() {
+        for line in [DOWNLOAD, PLAYLIST, SUBTITLES] {
+            assert_eq!(find_timestamps(line), Vec::<f64>::new(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn classifies_lines() {
+        let content = [
+            DOWNLOAD, TIMESTAMP, "the note", PAUSED, PLAYLIST, "see 7:45", SUBTITLES,
+        ]
+        .join("\n");
+        let timed = timed_lines(&content)
+            .into_iter()
+            .map(|timed| (timed.index, timed.position, timed.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timed,
+            vec![
+                (1, 125.0, LineKind::Timestamp),
+                (3, 133.0, LineKind::Event("paused".to_string())),
+                (5, 465.0, LineKind::Text),
+            ]
+        );
+    }
+}
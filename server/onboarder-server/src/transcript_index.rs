@@ -0,0 +1,414 @@
+use crate::catalog::Catalog;
+use crate::catalog::FileKind;
+use crate::ranking;
+use crate::subtitles;
+use crate::subtitles::Segment;
+use crate::timestamps::deep_link;
+use chrono::DateTime;
+use chrono::Local;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::Mutex;
+use tracing::info;
+use tracing::warn;
+
+/// Added to the score of a hit containing the query as written.
+const PHRASE_BONUS: f64 = 5.0;
+/// Added when the match is within the segment itself rather than spilling
+/// into the next, so the segment where it starts is the hit.
+const OWN_SEGMENT_BONUS: f64 = 1.0;
+
+/// A subtitle file of a catalogued video.
+#[derive(Clone, Debug)]
+pub struct Source {
+    pub video_id: String,
+    pub title: Option<String>,
+    pub path: PathBuf,
+    pub modified: Option<DateTime<Local>>,
+}
+
+struct IndexedFile {
+    source: Source,
+    segments: Vec<Segment>,
+}
+
+/// One segment, searched together with the segment after it so phrases
+/// split across caption lines are still found.
+struct Doc {
+    path: PathBuf,
+    segment: usize,
+    /// Tokens of the segment and the next one.
+    text: String,
+    /// Tokens of the segment alone.
+    own: String,
+    terms: HashMap<String, u32>,
+    len: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TranscriptHit {
+    pub video_id: String,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    /// In seconds.
+    pub start: f64,
+    /// In seconds.
+    pub end: f64,
+    pub text: String,
+    /// The video on YouTube, starting at the hit.
+    pub url: String,
+    pub score: f64,
+}
+
+/// An inverted index over the segments of every downloaded transcript.
+/// Files are only parsed again when their modification time changes, and
+/// that includes files that failed to parse.
+#[derive(Default)]
+pub struct TranscriptIndex {
+    files: BTreeMap<PathBuf, IndexedFile>,
+    /// Modification times of the files that failed to parse.
+    failed: HashMap<PathBuf, Option<DateTime<Local>>>,
+    docs: Vec<Doc>,
+    postings: HashMap<String, Vec<usize>>,
+    average_len: f64,
+}
+
+impl TranscriptIndex {
+    /// Of `sources`, the ones not indexed yet or changed since.
+    pub fn stale(&self, sources: &[Source]) -> Vec<Source> {
+        sources
+            .iter()
+            .filter(|source| {
+                let indexed = self
+                    .files
+                    .get(&source.path)
+                    .is_some_and(|file| file.source.modified == source.modified);
+                let failed = self.failed.get(&source.path) == Some(&source.modified);
+                !indexed && !failed
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Makes the index cover exactly `sources`, taking the segments of the
+    /// stale ones from `parsed`. The ones that failed to parse are skipped
+    /// until they change.
+    pub fn update(
+        &mut self,
+        sources: &[Source],
+        parsed: Vec<(Source, Result<Vec<Segment>, String>)>,
+    ) {
+        let before = self.files.len();
+        let wanted = |path: &PathBuf| sources.iter().any(|source| &source.path == path);
+        self.files.retain(|path, _| wanted(path));
+        self.failed.retain(|path, _| wanted(path));
+        let mut removed = before - self.files.len();
+        let mut changed = 0;
+        for (source, segments) in parsed {
+            match segments {
+                Ok(segments) => {
+                    self.failed.remove(&source.path);
+                    self.files
+                        .insert(source.path.clone(), IndexedFile { source, segments });
+                    changed += 1;
+                }
+                Err(err) => {
+                    warn!("Skipping transcript until it changes: {}", err);
+                    if self.files.remove(&source.path).is_some() {
+                        removed += 1;
+                    }
+                    self.failed.insert(source.path, source.modified);
+                }
+            }
+        }
+        if changed == 0 && removed == 0 {
+            return;
+        }
+        // Titles may have been learned since a file was indexed
+        for source in sources {
+            if let Some(file) = self.files.get_mut(&source.path) {
+                file.source.title = source.title.clone();
+            }
+        }
+        self.rebuild();
+        info!(
+            "Transcript index: {} files parsed, {} dropped, {} segments",
+            changed,
+            removed,
+            self.docs.len()
+        );
+    }
+
+    fn rebuild(&mut self) {
+        self.docs.clear();
+        self.postings.clear();
+        for (path, file) in &self.files {
+            for (index, segment) in file.segments.iter().enumerate() {
+                let own = ranking::words(&segment.text);
+                let mut tokens = own.clone();
+                if let Some(next) = file.segments.get(index + 1) {
+                    tokens.extend(ranking::words(&next.text));
+                }
+                let mut terms = HashMap::new();
+                for token in &tokens {
+                    *terms.entry(token.clone()).or_insert(0) += 1;
+                }
+                let id = self.docs.len();
+                for term in terms.keys() {
+                    self.postings.entry(term.clone()).or_default().push(id);
+                }
+                self.docs.push(Doc {
+                    path: path.clone(),
+                    segment: index,
+                    text: tokens.join(" "),
+                    own: own.join(" "),
+                    terms,
+                    len: tokens.len(),
+                });
+            }
+        }
+        self.average_len = if self.docs.is_empty() {
+            0.0
+        } else {
+            self.docs.iter().map(|doc| doc.len).sum::<usize>() as f64 / self.docs.len() as f64
+        };
+    }
+
+    /// Segments containing every word of `query`, best first. Adjacent
+    /// segments matching the same words are one hit.
+    pub fn search(&self, query: &str, limit: usize) -> Vec<TranscriptHit> {
+        let terms = ranking::words(query);
+        let Some(rarest) = terms
+            .iter()
+            .min_by_key(|term| self.postings.get(*term).map_or(0, Vec::len))
+        else {
+            return Vec::new();
+        };
+        let phrase = terms.join(" ");
+        let total = self.docs.len() as f64;
+        let mut scored = self
+            .postings
+            .get(rarest)
+            .into_iter()
+            .flatten()
+            .filter_map(|&id| {
+                let doc = &self.docs[id];
+                let mut score = 0.0;
+                for term in &terms {
+                    let tf = *doc.terms.get(term)? as f64;
+                    let df = self.postings.get(term).map_or(0, Vec::len) as f64;
+                    let idf = ranking::idf(total, df);
+                    score += ranking::bm25(idf, tf, doc.len as f64, self.average_len);
+                }
+                if terms.len() > 1 && doc.text.contains(&phrase) {
+                    score += PHRASE_BONUS;
+                }
+                let own = doc.own.split(' ').collect::<Vec<&str>>();
+                if doc.own.contains(&phrase)
+                    || terms.iter().all(|term| own.contains(&term.as_str()))
+                {
+                    score += OWN_SEGMENT_BONUS;
+                }
+                Some((id, score))
+            })
+            .collect::<Vec<(usize, f64)>>();
+
+        // A match in one segment usually also matches the window before it
+        scored.sort_by_key(|(id, _)| *id);
+        let mut hits: Vec<(usize, f64)> = Vec::new();
+        for (id, score) in scored {
+            match hits.last_mut() {
+                Some((last, last_score))
+                    if self.docs[*last].path == self.docs[id].path
+                        && self.docs[*last].segment + 1 == self.docs[id].segment =>
+                {
+                    if score > *last_score {
+                        *last = id;
+                        *last_score = score;
+                    }
+                }
+                _ => hits.push((id, score)),
+            }
+        }
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits.into_iter()
+            .take(limit)
+            .map(|(id, score)| {
+                let doc = &self.docs[id];
+                let file = &self.files[&doc.path];
+                let segment = &file.segments[doc.segment];
+                TranscriptHit {
+                    video_id: file.source.video_id.clone(),
+                    title: file.source.title.clone(),
+                    language: subtitles::language(&doc.path),
+                    start: segment.start,
+                    end: segment.end,
+                    text: segment.text.clone(),
+                    url: deep_link(&file.source.video_id, segment.start),
+                    score,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Brings `index` up to date with the subtitle files in `catalog`. Checking
+/// which files are still there and parsing them happens without holding
+/// either lock.
+pub async fn refresh(index: &Mutex<TranscriptIndex>, catalog: &Mutex<Catalog>) {
+    let candidates = catalog
+        .lock()
+        .await
+        .entries()
+        .flat_map(|entry| {
+            entry
+                .files
+                .iter()
+                .filter(|file| {
+                    file.kind == FileKind::Subtitle && subtitles::is_transcript_file(&file.path)
+                })
+                .map(|file| Source {
+                    video_id: entry.video_id.clone(),
+                    title: entry.title.clone(),
+                    path: file.path.clone(),
+                    modified: file.modified,
+                })
+        })
+        .collect::<Vec<Source>>();
+    let sources = tokio::task::spawn_blocking(move || {
+        candidates
+            .into_iter()
+            .filter(|source| source.path.exists())
+            .collect::<Vec<Source>>()
+    })
+    .await
+    .unwrap_or_default();
+    let stale = index.lock().await.stale(&sources);
+    let parsed = tokio::task::spawn_blocking(move || {
+        stale
+            .into_iter()
+            .map(|source| {
+                let segments = subtitles::parse_file(&source.path);
+                (source, segments)
+            })
+            .collect::<Vec<(Source, Result<Vec<Segment>, String>)>>()
+    })
+    .await
+    .unwrap_or_default();
+    index.lock().await.update(&sources, parsed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(video_id: &str, path: &str) -> Source {
+        Source {
+            video_id: video_id.to_string(),
+            title: Some(format!("Video {}", video_id)),
+            path: PathBuf::from(path),
+            modified: None,
+        }
+    }
+
+    fn segments(lines: &[&str]) -> Vec<Segment> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(index, text)| Segment {
+                start: index as f64 * 10.0,
+                end: index as f64 * 10.0 + 10.0,
+                text: text.to_string(),
+            })
+            .collect()
+    }
+
+    fn index() -> TranscriptIndex {
+        let a = source("aaaaaaaaaaa", "/videos/A [aaaaaaaaaaa].en.vtt");
+        let b = source("bbbbbbbbbbb", "/videos/B [bbbbbbbbbbb].en.vtt");
+        let mut index = TranscriptIndex::default();
+        index.update(
+            &[a.clone(), b.clone()],
+            vec![
+                (
+                    a,
+                    Ok(segments(&[
+                        "welcome back to the channel",
+                        "today we talk about the borrow",
+                        "checker and why it exists",
+                        "the checker is strict about borrow rules",
+                    ])),
+                ),
+                (
+                    b,
+                    Ok(segments(&[
+                        "a borrow of a borrow",
+                        "nothing else is said here",
+                    ])),
+                ),
+            ],
+        );
+        index
+    }
+
+    fn found(hits: &[TranscriptHit]) -> Vec<(&str, f64)> {
+        hits.iter()
+            .map(|hit| (hit.video_id.as_str(), hit.start))
+            .collect()
+    }
+
+    #[test]
+    fn every_word_must_match() {
+        let index = index();
+        assert_eq!(
+            found(&index.search("channel", 10)),
+            vec![("aaaaaaaaaaa", 0.0)]
+        );
+        assert!(index.search("borrow cake", 10).is_empty());
+        assert!(index.search("", 10).is_empty());
+        assert_eq!(index.search("BORROW", 1).len(), 1);
+    }
+
+    #[test]
+    fn phrases_across_caption_lines_win() {
+        let index = index();
+        let hits = index.search("borrow checker", 10);
+        // The phrase starts in the second segment and ends in the third
+        assert_eq!(hits[0].video_id, "aaaaaaaaaaa");
+        assert_eq!(hits[0].start, 10.0);
+        assert_eq!(hits[0].text, "today we talk about the borrow");
+        assert_eq!(
+            hits[0].url,
+            "https://www.youtube.com/watch?v=aaaaaaaaaaa&t=10s"
+        );
+        assert_eq!(hits[0].language.as_deref(), Some("en"));
+        assert!(hits
+            .iter()
+            .all(|hit| hit.video_id != "bbbbbbbbbbb" || hit.start != 10.0));
+        assert!(hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+    }
+
+    #[test]
+    fn failed_files_wait_until_they_change() {
+        let mut index = index();
+        let a = source("aaaaaaaaaaa", "/videos/A [aaaaaaaaaaa].en.vtt");
+        let mut b = source("bbbbbbbbbbb", "/videos/B [bbbbbbbbbbb].en.vtt");
+        b.modified = Some(Local::now());
+        let sources = [a, b.clone()];
+        assert_eq!(index.stale(&sources).len(), 1);
+        index.update(&sources, vec![(b.clone(), Err("unreadable".to_string()))]);
+        assert!(index.stale(&sources).is_empty());
+        assert!(index.search("nothing", 10).is_empty());
+
+        b.modified = Some(Local::now() + chrono::Duration::seconds(1));
+        assert_eq!(index.stale(&[b.clone()]).len(), 1);
+        index.update(&[b.clone()], vec![(b, Ok(segments(&["fixed now"])))]);
+        assert_eq!(
+            found(&index.search("fixed", 10)),
+            vec![("bbbbbbbbbbb", 0.0)]
+        );
+        assert!(index.search("channel", 10).is_empty());
+    }
+}